@@ -0,0 +1,4 @@
+{{/* Name of the webhook Service, its Certificate and Issuer */}}
+{{- define "moodle-operator.webhookName" -}}
+{{- printf "%s-moodle-operator-webhook" .Release.Name | trunc 63 | trimSuffix "-" -}}
+{{- end -}}
@@ -0,0 +1,411 @@
+use std::{io::Read, path::PathBuf, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use kube::{
+    api::{Patch, PatchParams},
+    config::KubeConfigOptions,
+    Api, Client, ResourceExt,
+};
+use kube_runtime::wait::{await_condition, conditions::is_crd_established};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    config::{Config, SpecDefaults},
+    crds::{
+        conversion::{convert, API_VERSION_V1},
+        crd::Moodle,
+        definitions, ConversionWebhook,
+    },
+    reconciller::{apply::FIELD_MANAGER, manifests::Manifests},
+    server::tls_acceptor,
+};
+
+/// Kubernetes operator managing Moodle sites
+#[derive(Debug, Parser)]
+#[command(name = "operator", version)]
+pub struct Cli {
+    /// Defaults to `run`
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the controller and its HTTP servers, configured from the environment
+    Run,
+    /// Manage the CustomResourceDefinitions of the operator
+    #[command(subcommand)]
+    Crd(CrdCommand),
+    /// Print the operator version and the Moodle API versions it serves
+    Version,
+    /// Load the configuration from the environment and report problems without starting
+    CheckConfig,
+    /// Print the child objects the operator would create for Moodle objects, without a cluster
+    Render {
+        /// YAML file holding one or more Moodle objects, `-` for stdin
+        #[arg(short = 'f', long = "filename")]
+        file: PathBuf,
+        /// Namespace of objects that do not set one
+        #[arg(short = 'n', long, default_value = "default")]
+        namespace: String,
+    },
+    /// Dry-run apply Moodle objects against a cluster and print what would change
+    Plan {
+        /// YAML file holding one or more Moodle objects, `-` for stdin
+        #[arg(short = 'f', long = "filename")]
+        file: PathBuf,
+        /// Namespace of objects that do not set one
+        #[arg(short = 'n', long, default_value = "default")]
+        namespace: String,
+        /// Kubeconfig context, defaults to the current one
+        #[arg(long)]
+        context: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CrdCommand {
+    /// Print the CustomResourceDefinitions as YAML
+    Print {
+        #[command(flatten)]
+        conversion: ConversionArgs,
+    },
+    /// Server-side apply the CustomResourceDefinitions and wait until they are Established
+    Install {
+        /// Seconds to wait for each definition to become Established
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+        #[command(flatten)]
+        conversion: ConversionArgs,
+    },
+}
+
+/// Conversion webhook of the CRDs; without it only the storage version is served
+#[derive(Debug, Default, PartialEq, Args)]
+pub struct ConversionArgs {
+    /// Serve every Moodle version, converted by the operator Service `<namespace>/<name>`
+    #[arg(long, value_name = "NAMESPACE/NAME")]
+    pub webhook_service: Option<String>,
+    /// Port of the webhook Service
+    #[arg(long, default_value_t = 8443, requires = "webhook_service")]
+    pub webhook_port: i32,
+    /// PEM file with the CA of the webhook certificate, unless e.g. cert-manager injects it
+    #[arg(long, requires = "webhook_service")]
+    pub ca_bundle: Option<PathBuf>,
+}
+
+impl ConversionArgs {
+    pub fn webhook(&self) -> Result<Option<ConversionWebhook>> {
+        let Some(service) = &self.webhook_service else {
+            return Ok(None);
+        };
+        let (namespace, name) = service
+            .split_once('/')
+            .filter(|(namespace, name)| !namespace.is_empty() && !name.is_empty())
+            .with_context(|| format!("Webhook service '{service}' is not namespace/name"))?;
+        let ca_bundle = match &self.ca_bundle {
+            Some(path) => Some(
+                std::fs::read(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?,
+            ),
+            None => None,
+        };
+        Ok(Some(ConversionWebhook {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            port: self.webhook_port,
+            ca_bundle,
+        }))
+    }
+}
+
+/// Every CRD as a multi-document YAML stream
+pub fn print_crds(conversion: Option<&ConversionWebhook>) -> Result<String> {
+    let mut yaml = String::new();
+    for crd in definitions(conversion) {
+        yaml.push_str("---\n");
+        yaml.push_str(&serde_yaml::to_string(&crd)?);
+    }
+    Ok(yaml)
+}
+
+/// Apply every CRD with the operator's field manager, then wait for the API server to serve it
+pub async fn install_crds(timeout: Duration, conversion: Option<&ConversionWebhook>) -> Result<()> {
+    let client = Client::try_default().await?;
+    let api: Api<CustomResourceDefinition> = Api::all(client);
+
+    for crd in definitions(conversion) {
+        let name = crd.name_any();
+        // Forced, as installing replaces whatever definition an older release left behind
+        api.patch(
+            &name,
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(&crd),
+        )
+        .await
+        .with_context(|| format!("Failed to apply CustomResourceDefinition {name}"))?;
+        println!("customresourcedefinition/{name} applied");
+
+        tokio::time::timeout(
+            timeout,
+            await_condition(api.clone(), &name, is_crd_established()),
+        )
+        .await
+        .with_context(|| format!("{name} not Established within {timeout:?}"))??;
+        println!("customresourcedefinition/{name} established");
+    }
+    Ok(())
+}
+
+/// Operator version followed by the Moodle API versions it converts between, storage first
+pub fn version() -> String {
+    let served: Vec<String> = definitions(None)
+        .iter()
+        .flat_map(|crd| &crd.spec.versions)
+        .map(|version| version.name.clone())
+        .collect();
+    format!(
+        "operator {}\nmoodle.adorsys.com: {}",
+        env!("CARGO_PKG_VERSION"),
+        served.join(", ")
+    )
+}
+
+/// Parse the configuration and load the webhook certificates, as `run` would at startup
+pub fn check_config() -> Result<String> {
+    let config = Config::from_env()?;
+    if let Some(webhook) = &config.webhook {
+        tls_acceptor(&webhook.cert_dir).map_err(|e| {
+            anyhow!(
+                "Failed to load webhook certificate from {}: {e}",
+                webhook.cert_dir.display()
+            )
+        })?;
+    }
+
+    let watch = if config.watch_namespaces.is_empty() {
+        "all namespaces".to_string()
+    } else {
+        config.watch_namespaces.join(", ")
+    };
+    let mut report = vec![
+        format!("server: {}", config.bind_address),
+        format!("watch: {watch}"),
+        format!(
+            "label selector: {}",
+            config.moodle_label_selector.as_deref().unwrap_or("none")
+        ),
+        format!(
+            "reference label selector: {}",
+            config.reference_label_selector.as_deref().unwrap_or("none")
+        ),
+        format!(
+            "api token: {}",
+            if config.api_token.is_some() {
+                "set"
+            } else {
+                "unset"
+            }
+        ),
+        format!(
+            "webhook: {}",
+            config
+                .webhook
+                .as_ref()
+                .map_or("disabled".to_string(), |webhook| webhook
+                    .bind_address
+                    .to_string())
+        ),
+    ];
+    for (signal, otlp) in [
+        ("logs", &config.otlp_logs),
+        ("metrics", &config.otlp_metrics),
+        ("traces", &config.otlp_traces),
+    ] {
+        report.push(match otlp {
+            Some(otlp) => format!("otlp {signal}: {} over {:?}", otlp.endpoint, otlp.protocol),
+            None => format!("otlp {signal}: disabled"),
+        });
+    }
+    report.push("configuration ok".to_string());
+    Ok(report.join("\n"))
+}
+
+/// Client for the kubeconfig `context`, or the default configuration without one
+pub async fn client(context: Option<String>) -> Result<Client> {
+    let Some(context) = context else {
+        return Ok(Client::try_default().await?);
+    };
+    let config = kube::Config::from_kubeconfig(&KubeConfigOptions {
+        context: Some(context.clone()),
+        ..Default::default()
+    })
+    .await
+    .with_context(|| format!("Failed to load kubeconfig context {context}"))?;
+    Ok(Client::try_from(config)?)
+}
+
+/// Read `file`, or stdin for `-`
+pub fn read_input(file: &PathBuf) -> Result<String> {
+    let mut input = String::new();
+    if file.as_os_str() == "-" {
+        std::io::stdin().read_to_string(&mut input)?;
+    } else {
+        input = std::fs::read_to_string(file)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+    }
+    Ok(input)
+}
+
+/// Moodle objects of a YAML stream, in `v1` or `v1beta1`, converted to `v1`
+pub fn parse_moodles(input: &str, namespace: &str) -> Result<Vec<Moodle>> {
+    let mut moodles = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(input) {
+        let object = Value::deserialize(document)?;
+        if object.is_null() {
+            continue;
+        }
+        let object = convert(object, API_VERSION_V1).map_err(|e| anyhow!(e))?;
+        let mut moodle: Moodle = serde_json::from_value(object)?;
+        if moodle.metadata.namespace.is_none() {
+            moodle.metadata.namespace = Some(namespace.to_string());
+        }
+        moodles.push(moodle);
+    }
+    Ok(moodles)
+}
+
+/// Child manifests of every Moodle object in `input`, built as the reconciler would.
+/// - Referenced Secrets and ConfigMaps are not read, so pod templates carry no config hash.
+pub fn render(input: &str, namespace: &str, defaults: &SpecDefaults) -> Result<String> {
+    let mut yaml = String::new();
+    for moodle in parse_moodles(input, namespace)? {
+        let defaulted = defaults.applied(&moodle);
+        defaulted
+            .spec
+            .validate()
+            .with_context(|| format!("Invalid Moodle {}", moodle.name_any()))?;
+        yaml.push_str(&Manifests::build(&defaulted, None).to_yaml()?);
+    }
+    Ok(yaml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_parses_subcommands() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from(["operator"]).unwrap();
+        assert!(cli.command.is_none());
+
+        let cli = Cli::try_parse_from(["operator", "crd", "install", "--timeout", "5"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Crd(CrdCommand::Install { timeout: 5, .. }))
+        ));
+
+        let cli = Cli::try_parse_from([
+            "operator",
+            "crd",
+            "print",
+            "--webhook-service",
+            "ops/moodle-operator-webhook",
+        ])
+        .unwrap();
+        let Some(Command::Crd(CrdCommand::Print { conversion })) = cli.command else {
+            panic!("expected crd print");
+        };
+        let webhook = conversion.webhook().unwrap().unwrap();
+        assert_eq!((webhook.namespace.as_str(), webhook.port), ("ops", 8443));
+        assert!(
+            Cli::try_parse_from(["operator", "crd", "print", "--webhook-port", "9443"]).is_err()
+        );
+    }
+
+    fn defaults() -> SpecDefaults {
+        SpecDefaults {
+            image: "docker.io/bitnamilegacy/moodle:5.0".to_string(),
+            service_type: crate::crds::crd::ServiceType::ClusterIP,
+            db_ports: Default::default(),
+            pvc_suffix: "-data".to_string(),
+        }
+    }
+
+    /// Compare with a file under `testdata/`, rewriting it instead when `UPDATE_GOLDEN` is set
+    fn assert_golden(path: &str, actual: &str) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, actual).unwrap();
+        }
+        assert_eq!(actual, std::fs::read_to_string(&path).unwrap());
+    }
+
+    #[test]
+    fn test_render_golden_files() {
+        let input = include_str!("../testdata/render/moodle.yaml");
+        let rendered = render(input, "default", &defaults()).unwrap();
+        assert_golden("testdata/render/moodle.rendered.yaml", &rendered);
+    }
+
+    #[test]
+    fn test_render_rejects_invalid_spec() {
+        let input =
+            include_str!("../testdata/render/moodle.yaml").replace("user: moodle", "user: ''");
+        let error = render(&input, "default", &defaults()).unwrap_err();
+        assert!(format!("{error:#}").contains("spec.database.user"));
+    }
+
+    #[test]
+    fn test_print_crds() {
+        let parse = |yaml: String| -> CustomResourceDefinition {
+            serde_yaml::from_str(yaml.trim_start_matches("---\n")).unwrap()
+        };
+        let versions = |crd: &CustomResourceDefinition| -> Vec<(String, bool, bool)> {
+            crd.spec
+                .versions
+                .iter()
+                .map(|version| (version.name.clone(), version.served, version.storage))
+                .collect()
+        };
+
+        let crd = parse(print_crds(None).unwrap());
+        assert_eq!(crd.name_any(), "moodles.moodle.adorsys.com");
+        assert_eq!(
+            versions(&crd),
+            [("v1".into(), true, true), ("v1beta1".into(), false, false)]
+        );
+        assert!(crd.spec.conversion.is_none());
+
+        let webhook = ConversionWebhook {
+            namespace: "ops".to_string(),
+            name: "moodle-operator-webhook".to_string(),
+            port: 8443,
+            ca_bundle: None,
+        };
+        let crd = parse(print_crds(Some(&webhook)).unwrap());
+        assert_eq!(
+            versions(&crd),
+            [("v1".into(), true, true), ("v1beta1".into(), true, false)]
+        );
+        let conversion = crd.spec.conversion.unwrap();
+        assert_eq!(conversion.strategy, "Webhook");
+        let service = conversion.webhook.unwrap().client_config.unwrap().service;
+        assert_eq!(service.unwrap().path.as_deref(), Some("/convert"));
+    }
+
+    #[test]
+    fn test_chart_crds_match_print() {
+        // Regenerate with `UPDATE_GOLDEN=1 cargo test` after changing the CRD types
+        assert_golden(
+            "../../charts/moodle-operator/files/moodle-crd.yaml",
+            &print_crds(None).unwrap(),
+        );
+    }
+}
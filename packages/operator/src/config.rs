@@ -1,12 +1,99 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use kube::ResourceExt;
+use std::collections::BTreeMap;
 use std::env;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::crds::crd::{DatabaseType, Moodle, MoodleSpec, ServiceType};
+use crate::reconciller::references::WATCH_LABEL_SELECTOR;
+use crate::telemetry::{
+    logging::LogFormat,
+    otlp::{OtlpConfig, Signal},
+};
+
+/// HTTPS listener for admission webhooks
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub bind_address: SocketAddr,
+    pub cert_dir: PathBuf,
+}
+
+/// Coordination Lease deciding which replica runs the controllers
+#[derive(Debug, Clone)]
+pub struct LeaderElectionConfig {
+    pub namespace: String,
+    pub lease_name: String,
+    pub identity: String,
+    pub lease_duration: Duration,
+}
+
+/// Operator-wide values for Moodle spec fields left empty
+#[derive(Debug, Clone)]
+pub struct SpecDefaults {
+    pub image: String,
+    pub service_type: ServiceType,
+    pub db_ports: BTreeMap<DatabaseType, u16>,
+    /// Appended to the object name for an empty `pvcName`
+    pub pvc_suffix: String,
+}
+
+impl SpecDefaults {
+    /// Fill the empty fields of `spec`, belonging to the Moodle object `name`
+    pub fn apply(&self, name: &str, spec: &mut MoodleSpec) {
+        if spec.image.trim().is_empty() {
+            spec.image = self.image.clone();
+        }
+        if spec.service_type.is_none() {
+            spec.service_type = Some(self.service_type);
+        }
+        if spec.pvc_name.is_empty() {
+            spec.pvc_name = format!("{name}{}", self.pvc_suffix);
+        }
+        if spec.database.port == 0 {
+            let db_type = spec.database.db_type;
+            spec.database.port = self
+                .db_ports
+                .get(&db_type)
+                .copied()
+                .unwrap_or_else(|| db_type.default_port());
+        }
+    }
+
+    /// Copy of `moodle` with the defaults applied to its spec
+    pub fn applied(&self, moodle: &Moodle) -> Moodle {
+        let mut moodle = moodle.clone();
+        self.apply(&moodle.name_any(), &mut moodle.spec);
+        moodle
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub bind_address: SocketAddr,
-    pub log_exporter_endpoint: String,
-    pub metrics_exporter_endpoint: String,
+    pub log_format: LogFormat,
+    pub otlp_logs: Option<OtlpConfig>,
+    pub otlp_metrics: Option<OtlpConfig>,
+    pub otlp_traces: Option<OtlpConfig>,
+    pub metrics_pull_enabled: bool,
+    pub shutdown_timeout: Duration,
+    pub telemetry_flush_timeout: Duration,
+    pub task_max_failures: u32,
+    pub watch_namespaces: Vec<String>,
+    pub moodle_label_selector: Option<String>,
+    pub reference_label_selector: Option<String>,
+    pub reconcile_concurrency: u16,
+    pub reconcile_debounce: Duration,
+    pub resync_interval: Duration,
+    pub error_requeue_base: Duration,
+    pub error_requeue_max: Duration,
+    pub readiness_stale_after: Duration,
+    pub leader_election: Option<LeaderElectionConfig>,
+    pub api_token: Option<String>,
+    pub webhook: Option<WebhookConfig>,
+    pub spec_defaults: SpecDefaults,
 }
 
 impl Config {
@@ -20,18 +107,191 @@ impl Config {
             .parse()
             .with_context(|| format!("Failed to parse server bind address from '{addr_str}'"))?;
 
-        // Get log exporter endpoint
-        let log_exporter_endpoint = env::var("OTEL_LOGS_EXPORTER")
-            .unwrap_or_else(|_| "http://localhost:4318/v1/logs".into());
+        // Write stdout logs as plain text or as one JSON object per line
+        let log_format = parse_var("LOG_FORMAT", LogFormat::Text)?;
+
+        // OTLP exporters from the standard OTEL_EXPORTER_OTLP_* variables, None for stdout only
+        let otlp_logs = OtlpConfig::from_env(Signal::Logs);
+        let otlp_traces = OtlpConfig::from_env(Signal::Traces);
+
+        // Push metrics over OTLP and/or serve them for scraping on /metrics
+        let otlp_metrics = match parse_var("METRICS_PUSH_ENABLED", true)? {
+            true => OtlpConfig::from_env(Signal::Metrics),
+            false => None,
+        };
+        let metrics_pull_enabled = parse_var("METRICS_PULL_ENABLED", true)?;
+
+        // Time allowed for the controller and server to drain on shutdown
+        let shutdown_timeout = duration_secs("SHUTDOWN_TIMEOUT_SECS", 20)?;
+
+        // Time allowed for flushing logs and metrics before exiting
+        let telemetry_flush_timeout = duration_secs("TELEMETRY_FLUSH_TIMEOUT_SECS", 5)?;
+
+        // Consecutive failures of a background task before the operator exits
+        let task_max_failures = parse_var("TASK_MAX_FAILURES", 5)?;
 
-        // Get metrics exporter endpoint
-        let metrics_exporter_endpoint = env::var("OTEL_METRICS_EXPORTER")
-            .unwrap_or_else(|_| "http://localhost:9090/api/v1/otlp/v1/metrics".into());
+        // Namespaces to watch, comma separated; empty watches the whole cluster
+        let watch_namespaces = env::var("WATCH_NAMESPACES")
+            .map(|value| parse_list(&value))
+            .unwrap_or_default();
+
+        // Label selector restricting which Moodle objects are reconciled
+        let moodle_label_selector = env::var("MOODLE_LABEL_SELECTOR")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+
+        // Label selector for the watched Secrets and ConfigMaps, empty watches all of them
+        let reference_label_selector = env::var("REFERENCE_LABEL_SELECTOR")
+            .unwrap_or_else(|_| WATCH_LABEL_SELECTOR.to_string())
+            .trim()
+            .to_string();
+        let reference_label_selector =
+            Some(reference_label_selector).filter(|value| !value.is_empty());
+
+        // Maximum reconciles running at once across every watched namespace, 0 for unbounded
+        let reconcile_concurrency = parse_var("RECONCILE_CONCURRENCY", 0)?;
+
+        // Window used to coalesce bursts of events for the same Moodle
+        let reconcile_debounce =
+            parse_var("RECONCILE_DEBOUNCE_MS", 0).map(Duration::from_millis)?;
+
+        // Periodic requeue of healthy Moodle objects
+        let resync_interval = duration_secs("RESYNC_INTERVAL_SECS", 30)?;
+
+        // Requeue delay after a failed reconcile, doubled per consecutive failure up to the max
+        let error_requeue_base = duration_secs("ERROR_REQUEUE_BASE_SECS", 10)?;
+        let error_requeue_max = duration_secs("ERROR_REQUEUE_MAX_SECS", 300)?;
+
+        // Report not ready when no active Moodle object was reconciled for this long.
+        // Active objects are requeued at least every resync or error backoff, so the threshold
+        // never goes below twice the longest of them, a healthy controller would flap otherwise.
+        let readiness_stale_after = duration_secs("READINESS_STALE_SECS", 600)?
+            .max(2 * resync_interval.max(error_requeue_max));
+
+        // Only the replica holding the Lease runs the controllers, the others stand by
+        let leader_election = match parse_var("LEADER_ELECTION", false)? {
+            true => {
+                let lease_duration = duration_secs("LEADER_LEASE_DURATION_SECS", 15)?;
+                if lease_duration.is_zero() {
+                    bail!("LEADER_LEASE_DURATION_SECS must be at least 1");
+                }
+                Some(LeaderElectionConfig {
+                    namespace: env::var("POD_NAMESPACE")
+                        .context("LEADER_ELECTION needs POD_NAMESPACE for the Lease")?,
+                    lease_name: env::var("LEADER_LEASE_NAME")
+                        .unwrap_or_else(|_| "moodle-operator".to_string()),
+                    identity: env::var("POD_NAME")
+                        .context("LEADER_ELECTION needs POD_NAME to identify this replica")?,
+                    lease_duration,
+                })
+            }
+            false => None,
+        };
+
+        // Bearer token required by mutating API endpoints; they are disabled when unset
+        let api_token = env::var("API_TOKEN")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+
+        // Admission webhooks are served over HTTPS once a certificate directory is mounted
+        let webhook = match env::var("WEBHOOK_CERT_DIR") {
+            Ok(cert_dir) if !cert_dir.trim().is_empty() => {
+                let port = env::var("WEBHOOK_PORT").unwrap_or_else(|_| "8443".to_string());
+                let addr_str = format!("{host}:{port}");
+                let bind_address = addr_str.parse().with_context(|| {
+                    format!("Failed to parse webhook bind address from '{addr_str}'")
+                })?;
+                Some(WebhookConfig {
+                    bind_address,
+                    cert_dir: PathBuf::from(cert_dir.trim()),
+                })
+            }
+            _ => None,
+        };
+
+        // Values filled into Moodle specs by the mutating webhook and the reconciler
+        let mut db_ports = BTreeMap::new();
+        if let Ok(value) = env::var("DEFAULT_DB_PORTS") {
+            for item in parse_list(&value) {
+                let (db_type, port) = item
+                    .split_once('=')
+                    .with_context(|| format!("DEFAULT_DB_PORTS entry '{item}' is not type=port"))?;
+                let port = port.trim().parse().with_context(|| {
+                    format!("Failed to parse DEFAULT_DB_PORTS port for '{db_type}'")
+                })?;
+                let db_type: DatabaseType = db_type.parse().with_context(|| {
+                    format!("Unknown database type '{db_type}' in DEFAULT_DB_PORTS")
+                })?;
+                db_ports.insert(db_type, port);
+            }
+        }
+        let spec_defaults = SpecDefaults {
+            image: env::var("DEFAULT_IMAGE")
+                .unwrap_or_else(|_| "docker.io/bitnamilegacy/moodle:5.0".to_string()),
+            service_type: parse_var("DEFAULT_SERVICE_TYPE", ServiceType::ClusterIP)?,
+            db_ports,
+            pvc_suffix: env::var("DEFAULT_PVC_SUFFIX").unwrap_or_else(|_| "-data".to_string()),
+        };
 
         Ok(Config {
             bind_address,
-            log_exporter_endpoint,
-            metrics_exporter_endpoint,
+            log_format,
+            otlp_logs,
+            otlp_metrics,
+            otlp_traces,
+            metrics_pull_enabled,
+            shutdown_timeout,
+            telemetry_flush_timeout,
+            task_max_failures,
+            watch_namespaces,
+            moodle_label_selector,
+            reference_label_selector,
+            reconcile_concurrency,
+            reconcile_debounce,
+            resync_interval,
+            error_requeue_base,
+            error_requeue_max,
+            readiness_stale_after,
+            leader_election,
+            api_token,
+            webhook,
+            spec_defaults,
         })
     }
 }
+
+/// Parse `key` from the environment, falling back to `default` when unset
+fn parse_var<T>(key: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match env::var(key) {
+        Ok(value) => value
+            .parse()
+            .with_context(|| format!("Failed to parse {key} from '{value}'")),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Read a duration in whole seconds from `key`, falling back to `default`
+fn duration_secs(key: &str, default: u64) -> Result<Duration> {
+    parse_var(key, default).map(Duration::from_secs)
+}
+
+/// Split a comma separated list, dropping blanks and duplicates
+fn parse_list(value: &str) -> Vec<String> {
+    let mut items: Vec<String> = Vec::new();
+    for item in value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        if !items.iter().any(|existing| existing == item) {
+            items.push(item.to_string());
+        }
+    }
+    items
+}
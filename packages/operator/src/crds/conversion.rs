@@ -0,0 +1,112 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::crds::{crd, v1beta1};
+
+pub const API_VERSION_V1: &str = "moodle.adorsys.com/v1";
+pub const API_VERSION_V1BETA1: &str = "moodle.adorsys.com/v1beta1";
+
+impl From<crd::MoodleSpec> for v1beta1::MoodleSpec {
+    fn from(spec: crd::MoodleSpec) -> Self {
+        Self {
+            image: spec.image,
+            replicas: spec.replicas,
+            storage: v1beta1::StorageSpec {
+                pvc_name: spec.pvc_name,
+            },
+            networking: v1beta1::NetworkingSpec {
+                service_type: spec.service_type,
+            },
+            database: spec.database,
+            env_config_map: spec.env_config_map,
+        }
+    }
+}
+
+impl From<v1beta1::MoodleSpec> for crd::MoodleSpec {
+    fn from(spec: v1beta1::MoodleSpec) -> Self {
+        Self {
+            image: spec.image,
+            replicas: spec.replicas,
+            service_type: spec.networking.service_type,
+            pvc_name: spec.storage.pvc_name,
+            database: spec.database,
+            env_config_map: spec.env_config_map,
+        }
+    }
+}
+
+/// Convert a raw Moodle object to `desired_api_version`.
+/// - Only the spec is reshaped, metadata and status are copied untouched.
+pub fn convert(mut object: Value, desired_api_version: &str) -> Result<Value, String> {
+    let api_version = object["apiVersion"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    if api_version == desired_api_version {
+        return Ok(object);
+    }
+
+    let spec = object
+        .get_mut("spec")
+        .map(Value::take)
+        .ok_or_else(|| "object has no spec".to_string())?;
+    let converted = match (api_version.as_str(), desired_api_version) {
+        (API_VERSION_V1, API_VERSION_V1BETA1) => {
+            reshape::<crd::MoodleSpec, v1beta1::MoodleSpec>(spec)?
+        }
+        (API_VERSION_V1BETA1, API_VERSION_V1) => {
+            reshape::<v1beta1::MoodleSpec, crd::MoodleSpec>(spec)?
+        }
+        (from, to) => return Err(format!("cannot convert Moodle from '{from}' to '{to}'")),
+    };
+
+    object["spec"] = converted;
+    object["apiVersion"] = Value::String(desired_api_version.to_string());
+    Ok(object)
+}
+
+fn reshape<From, To>(spec: Value) -> Result<Value, String>
+where
+    From: DeserializeOwned + Into<To>,
+    To: Serialize,
+{
+    let spec: From = serde_json::from_value(spec).map_err(|e| format!("invalid spec: {e}"))?;
+    serde_json::to_value(spec.into()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn golden(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_convert_golden_files() {
+        let v1 = golden(include_str!("../../testdata/conversion/moodle-v1.json"));
+        let v1beta1 = golden(include_str!(
+            "../../testdata/conversion/moodle-v1beta1.json"
+        ));
+
+        assert_eq!(convert(v1.clone(), API_VERSION_V1BETA1).unwrap(), v1beta1);
+        assert_eq!(convert(v1beta1.clone(), API_VERSION_V1).unwrap(), v1);
+
+        // Round trips come back unchanged
+        let round_trip = convert(v1.clone(), API_VERSION_V1BETA1)
+            .and_then(|object| convert(object, API_VERSION_V1))
+            .unwrap();
+        assert_eq!(round_trip, v1);
+        assert_eq!(convert(v1.clone(), API_VERSION_V1).unwrap(), v1);
+    }
+
+    #[test]
+    fn test_convert_rejects_unknown_versions() {
+        let mut object = golden(include_str!("../../testdata/conversion/moodle-v1.json"));
+        assert!(convert(object.clone(), "moodle.adorsys.com/v2").is_err());
+
+        object["spec"]["database"] = Value::Null;
+        assert!(convert(object, API_VERSION_V1BETA1).is_err());
+    }
+}
@@ -1,12 +1,22 @@
-use kube::CustomResource;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Condition;
+use kube::{CustomResource, KubeSchema};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{fmt, str::FromStr};
 
-#[derive(CustomResource, Debug, Deserialize, Serialize, Clone, JsonSchema)]
+/// Changing this annotation, e.g. to the current time, requests an immediate reconcile
+pub const RECONCILE_AT_ANNOTATION: &str = "moodle.adorsys.com/reconcile-at";
+/// Set to `"true"` to stop the operator from changing the site's child resources
+pub const PAUSED_ANNOTATION: &str = "moodle.adorsys.com/paused";
+
+/// Desired configuration of the Moodle site
+#[derive(CustomResource, Debug, Deserialize, Serialize, Clone, KubeSchema)]
 #[kube(
     kind = "Moodle",
     group = "moodle.adorsys.com",
     version = "v1",
+    doc = "Moodle site managed by the operator",
     namespaced,
     shortname = "mdl",
     status = "MoodleStatus",
@@ -15,54 +25,389 @@ use serde::{Deserialize, Serialize};
 )]
 #[derive(PartialEq)]
 pub struct MoodleSpec {
+    /// Empty selects the operator's `DEFAULT_IMAGE`
+    #[serde(default)]
     pub image: String,
+    /// Number of Moodle pods
+    #[serde(default = "default_replicas")]
+    #[schemars(range(min = 0))]
     pub replicas: i32,
-    #[serde(rename = "serviceType")]
-    pub service_type: String,
-    #[serde(rename = "pvcName")]
+    /// Unset selects the operator's `DEFAULT_SERVICE_TYPE`
+    #[serde(
+        rename = "serviceType",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub service_type: Option<ServiceType>,
+    /// Existing PersistentVolumeClaim holding the Moodle data, fixed once created.
+    /// Empty selects the object name followed by the operator's `DEFAULT_PVC_SUFFIX`.
+    #[serde(rename = "pvcName", default, skip_serializing_if = "String::is_empty")]
+    #[schemars(length(min = 1))]
+    #[x_kube(validation = Rule::new("self == oldSelf").message("pvcName is immutable"))]
     pub pvc_name: String,
+    /// Database connection configuration
     pub database: DatabaseConfig,
+    /// ConfigMap whose entries are added to the Moodle container environment
+    #[serde(
+        rename = "envConfigMap",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub env_config_map: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, KubeSchema)]
+#[x_kube(
+    validation = Rule::new("has(self.password) || has(self.passwordSecretRef)")
+        .message("password or passwordSecretRef is required")
+)]
 pub struct DatabaseConfig {
+    /// Database server host name
+    #[schemars(length(min = 1))]
     pub host: String,
+    /// 0 selects the operator's default port for the database type
+    #[serde(default)]
     pub port: u16,
+    /// Database user Moodle connects as
+    #[schemars(length(min = 1))]
     pub user: String,
+    /// Used when `passwordSecretRef` is not set
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    #[schemars(length(min = 1))]
     pub password: String,
+    /// Secret key holding the password, rotating it rolls the pods
+    #[serde(
+        rename = "passwordSecretRef",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub password_secret_ref: Option<SecretKeyRef>,
+    /// Database backend, fixed once created
     #[serde(rename = "type")]
-    pub db_type: String,
+    #[x_kube(validation = Rule::new("self == oldSelf").message("database type is immutable"))]
+    pub db_type: DatabaseType,
+    /// Name of the database used by Moodle
+    #[schemars(length(min = 1))]
+    pub name: String,
+}
+
+/// Key of a Secret in the namespace of the Moodle object
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct SecretKeyRef {
+    #[schemars(length(min = 1))]
     pub name: String,
+    #[schemars(length(min = 1))]
+    pub key: String,
+}
+
+/// Kubernetes Service type exposing the Moodle site
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum ServiceType {
+    ClusterIP,
+    NodePort,
+    LoadBalancer,
+}
+
+/// Database backend, named as in Moodle's `dbtype` setting
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseType {
+    Mariadb,
+    Mysqli,
+    Pgsql,
+    Sqlsrv,
+    Auroramysql,
+}
+
+impl DatabaseType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DatabaseType::Mariadb => "mariadb",
+            DatabaseType::Mysqli => "mysqli",
+            DatabaseType::Pgsql => "pgsql",
+            DatabaseType::Sqlsrv => "sqlsrv",
+            DatabaseType::Auroramysql => "auroramysql",
+        }
+    }
+
+    /// Port the database usually listens on
+    pub fn default_port(self) -> u16 {
+        match self {
+            DatabaseType::Mariadb | DatabaseType::Mysqli | DatabaseType::Auroramysql => 3306,
+            DatabaseType::Pgsql => 5432,
+            DatabaseType::Sqlsrv => 1433,
+        }
+    }
+}
+
+impl fmt::Display for DatabaseType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Parse the names used in the CRD, e.g. from operator configuration
+impl FromStr for DatabaseType {
+    type Err = serde_json::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(Value::String(value.trim().to_string()))
+    }
+}
+
+impl FromStr for ServiceType {
+    type Err = serde_json::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(Value::String(value.trim().to_string()))
+    }
 }
 
+/// Observed state of the Moodle site
 #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, JsonSchema)]
 pub struct MoodleStatus {
-    ready_replicas: Option<i32>,
-    phase: Option<String>,
+    /// Number of ready Moodle pods
+    #[serde(rename = "readyReplicas")]
+    pub ready_replicas: Option<i32>,
+    /// Current phase of the Moodle site
+    pub phase: Option<String>,
+    /// Last `moodle.adorsys.com/reconcile-at` value acted upon
+    #[serde(
+        rename = "lastHandledReconcileAt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_handled_reconcile_at: Option<String>,
+    /// Latest observations of the Moodle site, e.g. `SpecValid` and `Paused`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+}
+
+fn default_replicas() -> i32 {
+    1
+}
+
+/// Why a field was rejected, named after the Kubernetes `StatusCause` reasons
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FieldErrorCode {
+    #[serde(rename = "FieldValueRequired")]
+    Required,
+    #[serde(rename = "FieldValueInvalid")]
+    Invalid,
+    #[serde(rename = "FieldValueForbidden")]
+    Immutable,
+}
+
+impl FieldErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Required => "FieldValueRequired",
+            Self::Invalid => "FieldValueInvalid",
+            Self::Immutable => "FieldValueForbidden",
+        }
+    }
+}
+
+/// One rejected field of a Moodle object, e.g. `spec.database.user`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    pub path: String,
+    pub code: FieldErrorCode,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(path: &str, code: FieldErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            path: path.to_string(),
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn required(path: &str) -> Self {
+        Self::new(path, FieldErrorCode::Required, "must not be empty")
+    }
+
+    pub fn immutable(path: &str) -> Self {
+        Self::new(path, FieldErrorCode::Immutable, "field is immutable")
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Every problem found in a Moodle spec, in field order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, thiserror::Error)]
+#[serde(transparent)]
+pub struct ValidationErrors(pub Vec<FieldError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let messages: Vec<String> = self.0.iter().map(FieldError::to_string).collect();
+        f.write_str(&messages.join("; "))
+    }
+}
+
+impl Moodle {
+    /// Reconciliation is paused through `PAUSED_ANNOTATION`
+    pub fn is_paused(&self) -> bool {
+        self.metadata
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(PAUSED_ANNOTATION))
+            .is_some_and(|value| value == "true")
+    }
 }
 
 impl MoodleSpec {
-    pub fn validate(&self) -> Result<(), String> {
+    /// Check the spec as the reconciler would apply it, reporting every invalid field
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
         if self.image.trim().is_empty() {
-            return Err("Image field must not be empty.".to_string());
+            errors.push(FieldError::required("spec.image"));
         }
         if self.replicas < 0 {
-            return Err("Replicas must be 0 or greater.".to_string());
+            errors.push(FieldError::new(
+                "spec.replicas",
+                FieldErrorCode::Invalid,
+                "must be 0 or greater",
+            ));
+        }
+        for (path, value) in [
+            ("spec.database.host", &self.database.host),
+            ("spec.database.user", &self.database.user),
+            ("spec.database.password", &self.database.password),
+            ("spec.database.name", &self.database.name),
+        ] {
+            let from_secret =
+                path == "spec.database.password" && self.database.password_secret_ref.is_some();
+            if value.is_empty() && !from_secret {
+                errors.push(FieldError::required(path));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+
+    /// Fields that may not change once the object exists, compared with `old`
+    pub fn validate_update(&self, old: &MoodleSpec) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        // Switching the volume or database engine would leave the site without its data
+        if old.pvc_name != self.pvc_name {
+            errors.push(FieldError::immutable("spec.pvcName"));
+        }
+        if old.database.db_type != self.database.db_type {
+            errors.push(FieldError::immutable("spec.database.type"));
         }
-        if self.service_type != "ClusterIP"
-            && self.service_type != "NodePort"
-            && self.service_type != "LoadBalancer"
-        {
-            return Err(format!("Invalid serviceType: {}", self.service_type));
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::CustomResourceExt;
+
+    #[test]
+    fn test_crd_schema_rules() {
+        let crd = serde_json::to_value(Moodle::crd()).unwrap();
+        let spec = &crd["spec"]["versions"][0]["schema"]["openAPIV3Schema"]["properties"]["spec"]
+            ["properties"];
+
+        let immutable = [&spec["pvcName"], &spec["database"]["properties"]["type"]];
+        for field in immutable {
+            assert_eq!(
+                field["x-kubernetes-validations"][0]["rule"],
+                "self == oldSelf"
+            );
         }
-        if self.database.db_type.is_empty()
-            || self.database.name.is_empty()
-            || self.database.user.is_empty()
-            || self.database.password.is_empty()
-            || self.database.host.is_empty()
-        {
-            return Err("Database config fields must not be empty.".to_string());
+        assert_eq!(
+            spec["database"]["x-kubernetes-validations"][0]["rule"],
+            "has(self.password) || has(self.passwordSecretRef)"
+        );
+        assert_eq!(spec["serviceType"]["enum"][2], "LoadBalancer");
+        assert_eq!(spec["database"]["properties"]["type"]["enum"][3], "sqlsrv");
+    }
+
+    #[test]
+    fn test_validate_reports_every_field() {
+        let spec: MoodleSpec = serde_json::from_value(serde_json::json!({
+            "image": "",
+            "replicas": -1,
+            "pvcName": "moodle-data",
+            "database": {
+                "host": "db", "user": "", "password": "",
+                "type": "pgsql", "name": "moodle"
+            }
+        }))
+        .unwrap();
+
+        let errors = spec.validate().unwrap_err();
+        let paths: Vec<&str> = errors.0.iter().map(|error| error.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "spec.image",
+                "spec.replicas",
+                "spec.database.user",
+                "spec.database.password"
+            ]
+        );
+        assert_eq!(errors.0[1].code, FieldErrorCode::Invalid);
+        assert_eq!(
+            serde_json::to_value(&errors.0[0]).unwrap()["code"],
+            "FieldValueRequired"
+        );
+    }
+
+    #[test]
+    fn test_is_paused() {
+        let mut moodle: Moodle = serde_json::from_value(serde_json::json!({
+            "apiVersion": "moodle.adorsys.com/v1",
+            "kind": "Moodle",
+            "metadata": { "name": "site" },
+            "spec": {
+                "pvcName": "moodle-data",
+                "database": {
+                    "host": "db", "user": "moodle", "password": "secret",
+                    "type": "pgsql", "name": "moodle"
+                }
+            }
+        }))
+        .unwrap();
+        assert!(!moodle.is_paused());
+
+        for (value, paused) in [("false", false), ("true", true)] {
+            moodle.metadata.annotations = Some(
+                [(PAUSED_ANNOTATION.to_string(), value.to_string())]
+                    .into_iter()
+                    .collect(),
+            );
+            assert_eq!(moodle.is_paused(), paused);
         }
-        Ok(())
+    }
+
+    #[test]
+    fn test_database_type_from_str() {
+        assert_eq!(
+            "pgsql".parse::<DatabaseType>().unwrap(),
+            DatabaseType::Pgsql
+        );
+        assert_eq!(DatabaseType::Auroramysql.to_string(), "auroramysql");
+        assert!("oracle".parse::<DatabaseType>().is_err());
     }
 }
@@ -1 +1,57 @@
+use k8s_openapi::{
+    apiextensions_apiserver::pkg::apis::apiextensions::v1::{
+        CustomResourceConversion, CustomResourceDefinition, ServiceReference, WebhookClientConfig,
+        WebhookConversion,
+    },
+    ByteString,
+};
+use kube::{core::crd::merge_crds, CustomResourceExt};
+
+pub mod conversion;
 pub mod crd;
+pub mod v1beta1;
+
+/// Operator Service the API server sends Moodle ConversionReviews to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionWebhook {
+    pub namespace: String,
+    pub name: String,
+    pub port: i32,
+    /// PEM certificates trusted for the webhook, left out when e.g. cert-manager injects them
+    pub ca_bundle: Option<Vec<u8>>,
+}
+
+/// Every CustomResourceDefinition owned by the operator
+pub fn definitions(conversion: Option<&ConversionWebhook>) -> Vec<CustomResourceDefinition> {
+    vec![moodle_crd(conversion)]
+}
+
+/// The Moodle CRD with `v1` as the storage version.
+/// - With a conversion webhook every version is served, converted through the operator's
+///   `/convert` endpoint.
+/// - Without one only `v1` is served, as the API server would otherwise store `v1beta1` objects
+///   unconverted as `v1`.
+pub fn moodle_crd(conversion: Option<&ConversionWebhook>) -> CustomResourceDefinition {
+    let mut crd = merge_crds(vec![crd::Moodle::crd(), v1beta1::Moodle::crd()], "v1")
+        .expect("Moodle versions share group, kind and scope");
+    for version in &mut crd.spec.versions {
+        version.served = version.storage || conversion.is_some();
+    }
+    crd.spec.conversion = conversion.map(|webhook| CustomResourceConversion {
+        strategy: "Webhook".to_string(),
+        webhook: Some(WebhookConversion {
+            conversion_review_versions: vec!["v1".to_string()],
+            client_config: Some(WebhookClientConfig {
+                service: Some(ServiceReference {
+                    namespace: webhook.namespace.clone(),
+                    name: webhook.name.clone(),
+                    path: Some("/convert".to_string()),
+                    port: Some(webhook.port),
+                }),
+                ca_bundle: webhook.ca_bundle.clone().map(ByteString),
+                url: None,
+            }),
+        }),
+    });
+    crd
+}
@@ -0,0 +1,69 @@
+use kube::{CustomResource, KubeSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::crds::crd::{DatabaseConfig, MoodleStatus, ServiceType};
+
+/// Desired configuration of the Moodle site, grouped into sections leaving room for storage,
+/// networking and backup options
+#[derive(CustomResource, Debug, Deserialize, Serialize, Clone, KubeSchema)]
+#[kube(
+    kind = "Moodle",
+    group = "moodle.adorsys.com",
+    version = "v1beta1",
+    doc = "Moodle site managed by the operator",
+    namespaced,
+    shortname = "mdl",
+    status = "MoodleStatus",
+    derive = "PartialEq",
+    printcolumn = r#"{"name":"Phase", "type":"string", "description":"Status", "jsonPath":".status.phase"}"#
+)]
+#[derive(PartialEq)]
+pub struct MoodleSpec {
+    /// Empty selects the operator's `DEFAULT_IMAGE`
+    #[serde(default)]
+    pub image: String,
+    /// Number of Moodle pods
+    #[serde(default = "default_replicas")]
+    #[schemars(range(min = 0))]
+    pub replicas: i32,
+    #[serde(default)]
+    pub storage: StorageSpec,
+    #[serde(default)]
+    pub networking: NetworkingSpec,
+    /// Database connection configuration
+    pub database: DatabaseConfig,
+    /// ConfigMap whose entries are added to the Moodle container environment
+    #[serde(
+        rename = "envConfigMap",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub env_config_map: Option<String>,
+}
+
+/// Volumes holding the Moodle data
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, KubeSchema)]
+pub struct StorageSpec {
+    /// Existing PersistentVolumeClaim holding the Moodle data, fixed once created.
+    /// Empty selects the object name followed by the operator's `DEFAULT_PVC_SUFFIX`.
+    #[serde(rename = "pvcName", default, skip_serializing_if = "String::is_empty")]
+    #[schemars(length(min = 1))]
+    #[x_kube(validation = Rule::new("self == oldSelf").message("pvcName is immutable"))]
+    pub pvc_name: String,
+}
+
+/// How the Moodle site is exposed
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, KubeSchema)]
+pub struct NetworkingSpec {
+    /// Unset selects the operator's `DEFAULT_SERVICE_TYPE`
+    #[serde(
+        rename = "serviceType",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub service_type: Option<ServiceType>,
+}
+
+fn default_replicas() -> i32 {
+    1
+}
@@ -1,17 +1,49 @@
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error("Failed to create replicaset: {0}")]
-    ReplicaSetCreationFailed(#[from] kube::Error),
+    #[error("Failed to apply {kind} {name}: {source}")]
+    ApplyFailed {
+        kind: String,
+        name: String,
+        #[source]
+        source: kube::Error,
+    },
 
-    #[error("Failed to get ReplicaSet: {0}")]
-    ReplicaSetGetFailed(kube::Error),
+    #[error("Conflict applying {kind} {name}, fields are managed by {}: {message}", managers.join(", "))]
+    ApplyConflict {
+        kind: String,
+        name: String,
+        managers: Vec<String>,
+        message: String,
+    },
+
+    #[error("Failed to read {kind} {name}: {source}")]
+    ReadFailed {
+        kind: String,
+        name: String,
+        #[source]
+        source: kube::Error,
+    },
+
+    #[error("Failed to delete outdated Pod {name}: {source}")]
+    PodDeleteFailed {
+        name: String,
+        #[source]
+        source: kube::Error,
+    },
+
+    #[error("Failed to update Moodle status: {0}")]
+    StatusUpdateFailed(#[from] kube::Error),
 }
 
 impl Error {
-    pub fn is_not_found(&self) -> bool {
+    /// Short label identifying the error variant, used in metrics
+    pub fn kind(&self) -> &'static str {
         match self {
-            Error::ReplicaSetGetFailed(kube::Error::Api(api_err)) => matches!(api_err.code, 404),
-            _ => false,
+            Error::ApplyFailed { .. } => "apply_failed",
+            Error::ApplyConflict { .. } => "apply_conflict",
+            Error::ReadFailed { .. } => "read_failed",
+            Error::PodDeleteFailed { .. } => "pod_delete_failed",
+            Error::StatusUpdateFailed(_) => "status_update_failed",
         }
     }
 }
@@ -1,77 +1,234 @@
 use anyhow::Result;
+use clap::Parser;
 use kube::Client;
 use mimalloc::MiMalloc;
-use std::process;
+use std::{process::ExitCode, sync::Arc, time::Duration};
 use tokio::sync::mpsc;
-use tracing::{error, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+mod cli;
 mod config;
 mod crds;
 mod error;
+mod plan;
 mod reconciller;
 mod server;
+mod shutdown;
+mod supervisor;
 mod telemetry;
+mod webhook;
 use crate::{
+    cli::{Cli, Command, CrdCommand},
     config::Config,
-    reconciller::controller::controller_moodle_cluster,
-    server::start_server,
-    telemetry::{logging::LoggerHandle, metrics::MetricsHandle},
+    reconciller::{
+        backoff::ErrorBackoff,
+        leader::{lead, LeaderLease},
+        limit::ReconcileLimit,
+        state::ControllerState,
+        trigger::ReconcileTrigger,
+    },
+    server::{start_server, tls_acceptor, ServerState},
+    shutdown::{wait_for_signal, ShutdownReason},
+    supervisor::{RestartPolicy, Supervisor},
+    telemetry::{
+        logging::LoggerHandle, metrics::MetricsHandle, reconcile_metrics::ReconcileMetrics,
+        traces::TracerHandle,
+    },
 };
 
 #[derive(Clone)]
 struct Data {
     client: Client,
+    config: Config,
+    backoff: ErrorBackoff,
+    limit: ReconcileLimit,
+    metrics: ReconcileMetrics,
+    state: ControllerState,
+    trigger: ReconcileTrigger,
 }
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
+    match Cli::parse().command.unwrap_or(Command::Run) {
+        Command::Run => run().await,
+        Command::Crd(CrdCommand::Print { conversion }) => {
+            print!("{}", cli::print_crds(conversion.webhook()?.as_ref())?);
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Crd(CrdCommand::Install {
+            timeout,
+            conversion,
+        }) => {
+            cli::install_crds(Duration::from_secs(timeout), conversion.webhook()?.as_ref()).await?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Version => {
+            println!("{}", cli::version());
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::CheckConfig => {
+            println!("{}", cli::check_config()?);
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Render { file, namespace } => {
+            let defaults = Config::from_env()?.spec_defaults;
+            print!(
+                "{}",
+                cli::render(&cli::read_input(&file)?, &namespace, &defaults)?
+            );
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Plan {
+            file,
+            namespace,
+            context,
+        } => {
+            let defaults = Config::from_env()?.spec_defaults;
+            let moodles = cli::parse_moodles(&cli::read_input(&file)?, &namespace)?;
+            let client = cli::client(context).await?;
+            for site in plan::plan(&client, moodles, &defaults).await? {
+                print!("{site}");
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+/// Run the controller, HTTP server and webhook until a signal or a critical failure
+async fn run() -> Result<ExitCode> {
     // Load env configuration
     let config = Config::from_env()?;
 
+    // Initialize traces
+    let tracer_handle = TracerHandle::init(config.otlp_traces.as_ref());
     //Initialize logs
-    let logger_handle = LoggerHandle::init(&config.log_exporter_endpoint);
+    let logger_handle = LoggerHandle::init(
+        config.otlp_logs.as_ref(),
+        tracer_handle.tracer(),
+        config.log_format,
+    );
     // Initialize metrics
-    let metrics_handle = MetricsHandle::init(&config.metrics_exporter_endpoint);
-    let client = Client::try_default().await?;
-
-    // Create an mpsc channel for receiving errors from background tasks
-    let (tx, mut rx) = mpsc::channel::<String>(2);
+    let metrics_handle =
+        MetricsHandle::init(config.otlp_metrics.as_ref(), config.metrics_pull_enabled);
 
-    // Spawn Error Listener Task
-    let error_listener = tokio::spawn(async move {
-        if let Some(e) = rx.recv().await {
-            error!("Critical error received: {e}");
-            process::exit(1);
+    for (signal, otlp) in [
+        ("logs", &config.otlp_logs),
+        ("metrics", &config.otlp_metrics),
+        ("traces", &config.otlp_traces),
+    ] {
+        match otlp {
+            Some(otlp) => info!(
+                "Exporting {signal} to {} over {:?}",
+                otlp.endpoint, otlp.protocol
+            ),
+            None => info!("OTLP {signal} export disabled"),
         }
+    }
+    let client = Client::try_default().await?;
+
+    // Shared reconcile context, kept across controller restarts
+    let data = Arc::new(Data {
+        client,
+        config: config.clone(),
+        backoff: ErrorBackoff::new(config.error_requeue_base, config.error_requeue_max),
+        limit: ReconcileLimit::new(config.reconcile_concurrency),
+        metrics: ReconcileMetrics::init(),
+        state: ControllerState::default(),
+        trigger: ReconcileTrigger::default(),
     });
 
+    // Cancelled once to stop every background task
+    let shutdown = CancellationToken::new();
+
+    // Create an mpsc channel for tasks that keep failing after restarts
+    let (tx, mut rx) = mpsc::channel::<String>(2);
+    let policy = RestartPolicy {
+        max_failures: config.task_max_failures,
+        ..Default::default()
+    };
+    let supervisor = Supervisor::new(policy, shutdown.clone(), tx);
+
     // Spawn Server Task
     let server_bind_addr = config.bind_address;
-    let server_error_tx = tx.clone();
-    tokio::spawn(async move {
-        if let Err(e) = start_server(server_bind_addr).await {
-            let _ = server_error_tx.send(format!("server failed: {e}")).await;
-        }
+    let server_shutdown = shutdown.clone();
+    let server_state = ServerState {
+        health: supervisor.health(),
+        registry: metrics_handle.registry(),
+        log_level: logger_handle.log_level(),
+        data: data.clone(),
+    };
+    let webhook_state = server_state.clone();
+    let server_task = supervisor.spawn("server", move || {
+        start_server(
+            server_bind_addr,
+            server_shutdown.clone(),
+            server_state.clone(),
+            None,
+        )
     });
 
-    // Spawn Controller Task
-    let controller_error_tx = tx.clone();
-    tokio::spawn(async move {
-        info!("Starting Moodle controller");
-        if let Err(e) = controller_moodle_cluster(&client).await {
-            let _ = controller_error_tx
-                .send(format!("Controller error: {e}"))
-                .await;
-        }
+    // Spawn Webhook Task, serving the admission and conversion endpoints over HTTPS
+    let webhook_task = config.webhook.clone().map(|webhook| {
+        let webhook_shutdown = shutdown.clone();
+        supervisor.spawn("webhook", move || {
+            let shutdown = webhook_shutdown.clone();
+            let state = webhook_state.clone();
+            let webhook = webhook.clone();
+            async move {
+                let tls = tls_acceptor(&webhook.cert_dir)?;
+                start_server(webhook.bind_address, shutdown, state, Some(tls)).await
+            }
+        })
     });
 
-    let _ = error_listener.await;
+    // Spawn Controller Task, which waits for the leader lease when leader election is on
+    let controller_data = data.clone();
+    let controller_shutdown = shutdown.clone();
+    let leader_lease = config
+        .leader_election
+        .clone()
+        .map(|election| LeaderLease::new(data.client.clone(), election));
+    let controller_task = supervisor.spawn("controller", move || {
+        lead(
+            controller_data.clone(),
+            leader_lease.clone(),
+            controller_shutdown.clone(),
+        )
+    });
+
+    // Wait for a termination signal or the first critical error
+    let reason = tokio::select! {
+        reason = wait_for_signal() => reason,
+        Some(e) = rx.recv() => {
+            error!("Critical error received: {e}");
+            ShutdownReason::TaskFailed(e)
+        }
+    };
+    info!("Shutting down: {reason}");
+
+    // Stop the controller and drain the server within the shutdown deadline
+    shutdown.cancel();
+    let drained = tokio::time::timeout(config.shutdown_timeout, async {
+        let _ = tokio::join!(server_task, controller_task);
+        if let Some(webhook_task) = webhook_task {
+            let _ = webhook_task.await;
+        }
+    })
+    .await;
+    if drained.is_err() {
+        warn!(
+            "Background tasks did not stop within {:?}",
+            config.shutdown_timeout
+        );
+    }
 
     // Gracefully shutdown metrics and logging providers before exiting
-    metrics_handle.shutdown();
-    logger_handle.shutdown();
+    tracer_handle.shutdown(config.telemetry_flush_timeout);
+    metrics_handle.shutdown(config.telemetry_flush_timeout);
+    logger_handle.shutdown(config.telemetry_flush_timeout);
 
-    Ok(())
+    Ok(reason.exit_code())
 }
@@ -0,0 +1,372 @@
+use std::fmt::{self, Debug};
+
+use anyhow::{Context, Result};
+use k8s_openapi::api::apps::v1::ReplicaSet;
+use kube::{
+    api::{Patch, PatchParams},
+    Api, Client, Resource, ResourceExt,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    config::SpecDefaults,
+    crds::crd::Moodle,
+    reconciller::{
+        apply::FIELD_MANAGER,
+        manifests::Manifests,
+        references::{config_hash, References, CONFIG_HASH_ANNOTATION},
+    },
+};
+
+/// What applying one Moodle object would change in the cluster
+#[derive(Debug)]
+pub struct SitePlan {
+    pub namespace: String,
+    pub name: String,
+    pub action: Action,
+    /// Spec changes compared with the stored Moodle object
+    pub spec_changes: Vec<Change>,
+    /// Update rules the API server would reject, e.g. a changed immutable field
+    pub rejected: Vec<String>,
+    pub children: Vec<ResourcePlan>,
+}
+
+/// What a server-side dry-run apply reported for one child object
+#[derive(Debug)]
+pub struct ResourcePlan {
+    pub kind: String,
+    pub name: String,
+    pub action: Action,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Create,
+    Update,
+    Unchanged,
+}
+
+/// One changed field, addressed by its JSON pointer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub path: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+impl SitePlan {
+    /// The config-hash annotation changes, so the operator replaces the running pods.
+    /// - A ReplicaSet never replaces pods on its own, other template changes wait for new pods.
+    pub fn pods_roll(&self) -> bool {
+        self.template_changes().any(changes_config_hash)
+    }
+
+    /// The pod template changes without rolling the pods, so only new pods pick it up
+    pub fn new_pods_only(&self) -> bool {
+        !self.pods_roll() && self.template_changes().next().is_some()
+    }
+
+    fn template_changes(&self) -> impl Iterator<Item = &Change> {
+        self.children
+            .iter()
+            .flat_map(|child| &child.changes)
+            .filter(|change| change.path.starts_with("/spec/template"))
+    }
+
+    /// Reasons the change could interrupt the site, empty when it is safe to apply
+    pub fn disruptions(&self) -> Vec<String> {
+        let mut reasons = self.rejected.clone();
+        if self.pods_roll() {
+            reasons.push("pods are replaced".to_string());
+        }
+        for change in &self.spec_changes {
+            if change.path == "/spec/replicas" {
+                let replicas = |value: &Option<Value>| value.as_ref().and_then(Value::as_i64);
+                if replicas(&change.after) < replicas(&change.before) {
+                    reasons.push("replicas are scaled down".to_string());
+                }
+            }
+        }
+        reasons
+    }
+}
+
+/// Compare the desired state of every Moodle object with the cluster, without changing it
+pub async fn plan(
+    client: &Client,
+    moodles: Vec<Moodle>,
+    defaults: &SpecDefaults,
+) -> Result<Vec<SitePlan>> {
+    let mut plans = Vec::new();
+    for moodle in moodles {
+        let namespace = moodle.namespace().unwrap_or_default();
+        let name = moodle.name_any();
+        let moodle_api: Api<Moodle> = Api::namespaced(client.clone(), &namespace);
+        let live = moodle_api
+            .get_opt(&name)
+            .await
+            .with_context(|| format!("Failed to get Moodle {namespace}/{name}"))?;
+
+        let mut defaulted = defaults.applied(&moodle);
+        defaulted
+            .spec
+            .validate()
+            .with_context(|| format!("Invalid Moodle {namespace}/{name}"))?;
+
+        let (action, spec_changes, rejected) = match &live {
+            Some(live) => {
+                // Both sides defaulted, so fields filled by the operator do not show up
+                let stored = defaults.applied(live);
+                let changes = diff(
+                    Some(&json!({ "spec": stored.spec })),
+                    &json!({ "spec": defaulted.spec }),
+                );
+                let rejected = match defaulted.spec.validate_update(&stored.spec) {
+                    Ok(()) => Vec::new(),
+                    Err(errors) => errors.0.iter().map(ToString::to_string).collect(),
+                };
+                (action(Some(live), &changes), changes, rejected)
+            }
+            None => (Action::Create, Vec::new(), Vec::new()),
+        };
+
+        // Owner references of the children point at the stored object
+        defaulted.metadata.uid = live.and_then(|live| live.metadata.uid);
+        let config_hash = config_hash(client, &namespace, &References::of(&defaulted.spec)).await?;
+        let manifests = Manifests::build(&defaulted, config_hash.as_deref());
+
+        plans.push(SitePlan {
+            namespace: namespace.clone(),
+            name,
+            action,
+            spec_changes,
+            rejected,
+            children: vec![
+                dry_run(
+                    &Api::<ReplicaSet>::namespaced(client.clone(), &namespace),
+                    &manifests.replicaset,
+                )
+                .await?,
+            ],
+        });
+    }
+    Ok(plans)
+}
+
+/// Server-side dry-run apply `obj` with the operator's field manager and diff the result
+async fn dry_run<K>(api: &Api<K>, obj: &K) -> Result<ResourcePlan>
+where
+    K: Resource<DynamicType = ()> + Clone + Debug + Serialize + DeserializeOwned,
+{
+    let kind = K::kind(&()).to_string();
+    let name = obj.name_any();
+    let current = api
+        .get_opt(&name)
+        .await
+        .with_context(|| format!("Failed to get {kind} {name}"))?;
+    let applied = api
+        .patch(
+            &name,
+            &PatchParams::apply(FIELD_MANAGER).dry_run(),
+            &Patch::Apply(obj),
+        )
+        .await
+        .with_context(|| format!("Dry-run apply of {kind} {name} failed"))?;
+
+    let current = current.map(|current| comparable(&current)).transpose()?;
+    let changes = diff(current.as_ref(), &comparable(&applied)?);
+    Ok(ResourcePlan {
+        action: action(current.as_ref(), &changes),
+        kind,
+        name,
+        changes,
+    })
+}
+
+/// `change` sets, removes or replaces the config-hash annotation of the pod template,
+/// possibly by changing one of its parent objects
+fn changes_config_hash(change: &Change) -> bool {
+    let pointer = format!(
+        "/spec/template/metadata/annotations/{}",
+        CONFIG_HASH_ANNOTATION.replace('~', "~0").replace('/', "~1")
+    );
+    match pointer.strip_prefix(&change.path) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let before = change.before.as_ref().and_then(|value| value.pointer(rest));
+            let after = change.after.as_ref().and_then(|value| value.pointer(rest));
+            before != after
+        }
+        _ => false,
+    }
+}
+
+/// `obj` without the fields the API server maintains, which always differ
+fn comparable<K: Serialize>(obj: &K) -> Result<Value> {
+    let mut value = serde_json::to_value(obj)?;
+    if let Some(object) = value.as_object_mut() {
+        object.remove("status");
+    }
+    if let Some(metadata) = value["metadata"].as_object_mut() {
+        for field in [
+            "managedFields",
+            "resourceVersion",
+            "generation",
+            "uid",
+            "creationTimestamp",
+        ] {
+            metadata.remove(field);
+        }
+    }
+    Ok(value)
+}
+
+fn action<T>(current: Option<&T>, changes: &[Change]) -> Action {
+    match current {
+        None => Action::Create,
+        Some(_) if changes.is_empty() => Action::Unchanged,
+        Some(_) => Action::Update,
+    }
+}
+
+/// Fields that differ between `current` and `desired`, none when the object is created
+pub fn diff(current: Option<&Value>, desired: &Value) -> Vec<Change> {
+    let Some(current) = current else {
+        return Vec::new();
+    };
+    json_patch::diff(current, desired)
+        .iter()
+        .map(|operation| {
+            let path = operation.path().to_string();
+            Change {
+                before: current.pointer(&path).cloned(),
+                after: desired.pointer(&path).cloned(),
+                path,
+            }
+        })
+        .collect()
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Unchanged => "unchanged",
+        })
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.before, &self.after) {
+            (Some(before), Some(after)) => write!(f, "~ {}: {before} -> {after}", self.path),
+            (None, Some(after)) => write!(f, "+ {}: {after}", self.path),
+            (Some(before), None) => write!(f, "- {}: {before}", self.path),
+            (None, None) => write!(f, "~ {}", self.path),
+        }
+    }
+}
+
+impl fmt::Display for SitePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Moodle {}/{}: {}",
+            self.namespace, self.name, self.action
+        )?;
+        for change in &self.spec_changes {
+            writeln!(f, "    {change}")?;
+        }
+        for child in &self.children {
+            writeln!(f, "  {} {}: {}", child.kind, child.name, child.action)?;
+            for change in &child.changes {
+                writeln!(f, "    {change}")?;
+            }
+        }
+        let pods_roll = match (self.pods_roll(), self.new_pods_only()) {
+            (true, _) => "yes",
+            (false, true) => "no, pod template changes apply to new pods only",
+            (false, false) => "no",
+        };
+        writeln!(f, "  pods roll: {pods_roll}")?;
+        let disruptions = self.disruptions();
+        if disruptions.is_empty() {
+            writeln!(f, "  disruptive: no")
+        } else {
+            writeln!(f, "  disruptive: yes ({})", disruptions.join("; "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_reports_changed_paths() {
+        let current = json!({ "spec": { "replicas": 2, "template": { "image": "moodle:4.5" } } });
+        let desired = json!({ "spec": { "replicas": 1, "template": { "image": "moodle:5.0" } } });
+
+        let changes = diff(Some(&current), &desired);
+        let lines: Vec<String> = changes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            [
+                "~ /spec/replicas: 2 -> 1",
+                r#"~ /spec/template/image: "moodle:4.5" -> "moodle:5.0""#
+            ]
+        );
+        assert!(diff(Some(&current), &current).is_empty());
+        assert!(diff(None, &desired).is_empty());
+    }
+
+    #[test]
+    fn test_disruptions() {
+        let mut plan = SitePlan {
+            namespace: "default".to_string(),
+            name: "site".to_string(),
+            action: Action::Update,
+            spec_changes: diff(
+                Some(&json!({ "spec": { "replicas": 1 } })),
+                &json!({ "spec": { "replicas": 3 } }),
+            ),
+            rejected: Vec::new(),
+            children: vec![ResourcePlan {
+                kind: "ReplicaSet".to_string(),
+                name: "site".to_string(),
+                action: Action::Update,
+                changes: diff(
+                    Some(&json!({ "spec": { "replicas": 1 } })),
+                    &json!({ "spec": { "replicas": 3 } }),
+                ),
+            }],
+        };
+        assert!(!plan.pods_roll());
+        assert!(plan.disruptions().is_empty());
+
+        plan.spec_changes = diff(
+            Some(&json!({ "spec": { "replicas": 3 } })),
+            &json!({ "spec": { "replicas": 1 } }),
+        );
+        plan.children[0].changes = diff(
+            Some(&json!({ "spec": { "template": { "image": "moodle:4.5" } } })),
+            &json!({ "spec": { "template": { "image": "moodle:5.0" } } }),
+        );
+        assert!(!plan.pods_roll());
+        assert!(plan.new_pods_only());
+        assert_eq!(plan.disruptions(), ["replicas are scaled down"]);
+
+        // Adding the annotations object also adds the config hash
+        plan.children[0].changes = diff(
+            Some(&json!({ "spec": { "template": { "metadata": {} } } })),
+            &json!({ "spec": { "template": { "metadata": {
+                "annotations": { CONFIG_HASH_ANNOTATION: "abc" }
+            } } } }),
+        );
+        assert!(plan.pods_roll());
+        assert!(!plan.new_pods_only());
+        assert_eq!(plan.disruptions().len(), 2);
+    }
+}
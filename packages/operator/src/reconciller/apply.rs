@@ -0,0 +1,145 @@
+use std::fmt::Debug;
+
+use kube::api::{Patch, PatchParams};
+use kube::{Api, Resource, ResourceExt};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{info, instrument};
+
+use crate::error::Error;
+
+/// Field manager used for every server-side apply issued by the operator
+pub const FIELD_MANAGER: &str = "moodle-operator";
+
+/// Manager the API server recorded for children created by earlier operator releases.
+/// - Their plain create sent neither a field manager nor a user agent, which records an empty name.
+const LEGACY_FIELD_MANAGER: &str = "";
+
+/// Set on every child the operator applies.
+/// - Children without it still hold fields of the legacy create, which are taken over once.
+pub const SERVER_SIDE_APPLY_ANNOTATION: &str = "moodle.adorsys.com/server-side-apply";
+
+/// Server-side apply `obj` through `api`.
+/// - Ownership is never forced, so fields held by another manager surface as
+///   `Error::ApplyConflict` instead of being silently overwritten.
+/// - The one exception is a conflict with the operator's own legacy create on a child that was
+///   never applied, see `SERVER_SIDE_APPLY_ANNOTATION`. Its fields are taken over with a forced
+///   apply, which also sets the annotation so this happens only once.
+#[instrument(
+    name = "kube.apply",
+    skip_all,
+    fields(otel.kind = "client", k8s.kind = %K::kind(&()), k8s.name = %obj.name_any())
+)]
+pub async fn apply<K>(api: &Api<K>, obj: &K) -> Result<K, Error>
+where
+    K: Resource<DynamicType = ()> + Clone + Debug + Serialize + DeserializeOwned,
+{
+    let kind = K::kind(&()).to_string();
+    let name = obj.name_any();
+
+    let params = PatchParams::apply(FIELD_MANAGER);
+    let result = match api.patch(&name, &params, &Patch::Apply(obj)).await {
+        Err(kube::Error::Api(status))
+            if status.code == 409 && is_legacy_conflict(&conflicting_managers(&status.message)) =>
+        {
+            match api.get(&name).await {
+                Ok(live) if takeover_pending(&live) => {
+                    info!("Taking over fields of {kind} {name} from the operator's legacy create");
+                    api.patch(&name, &params.force(), &Patch::Apply(obj)).await
+                }
+                Ok(_) => Err(kube::Error::Api(status)),
+                Err(err) => Err(err),
+            }
+        }
+        result => result,
+    };
+
+    result.map_err(|err| match err {
+        kube::Error::Api(status) if status.code == 409 => Error::ApplyConflict {
+            managers: conflicting_managers(&status.message),
+            kind,
+            name,
+            message: status.message,
+        },
+        source => Error::ApplyFailed { kind, name, source },
+    })
+}
+
+/// Every conflicting manager is the operator itself, from before it used server-side apply
+fn is_legacy_conflict(managers: &[String]) -> bool {
+    !managers.is_empty()
+        && managers
+            .iter()
+            .all(|manager| manager == LEGACY_FIELD_MANAGER)
+}
+
+/// `live` was created by the legacy create and never applied since
+fn takeover_pending<K: Resource>(live: &K) -> bool {
+    !live
+        .annotations()
+        .contains_key(SERVER_SIDE_APPLY_ANNOTATION)
+}
+
+/// Extract the field managers named in a server-side apply conflict message,
+/// e.g. `Apply failed with 1 conflict: conflict with "kubectl" using apps/v1: .spec.replicas`
+fn conflicting_managers(message: &str) -> Vec<String> {
+    let mut managers = Vec::new();
+    for marker in ["conflict with \"", "conflicts with \""] {
+        for (idx, _) in message.match_indices(marker) {
+            let rest = &message[idx + marker.len()..];
+            if let Some(end) = rest.find('"') {
+                let manager = rest[..end].to_string();
+                if !managers.contains(&manager) {
+                    managers.push(manager);
+                }
+            }
+        }
+    }
+    managers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::api::apps::v1::ReplicaSet;
+
+    #[test]
+    fn test_conflicting_managers_single() {
+        let message = r#"Apply failed with 1 conflict: conflict with "kubectl-client-side-apply" using apps/v1: .spec.replicas"#;
+        assert_eq!(
+            conflicting_managers(message),
+            vec!["kubectl-client-side-apply".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_conflicting_managers_multiple() {
+        let message = "Apply failed with 2 conflicts: conflicts with \"helm\" using apps/v1:\n- .spec.replicas\nconflicts with \"kubectl-edit\" using apps/v1:\n- .spec.template";
+        assert_eq!(
+            conflicting_managers(message),
+            vec!["helm".to_string(), "kubectl-edit".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_legacy_conflict_is_taken_over_once() {
+        let message = r#"Apply failed with 1 conflict: conflict with "" using apps/v1: .spec.template.spec.containers[name="moodle"].image"#;
+        assert!(is_legacy_conflict(&conflicting_managers(message)));
+
+        let message = r#"Apply failed with 1 conflict: conflict with "unknown" using apps/v1: .spec.replicas"#;
+        assert!(!is_legacy_conflict(&conflicting_managers(message)));
+        let message = "Apply failed with 2 conflicts: conflicts with \"\" using apps/v1:\n- .spec.replicas\nconflicts with \"kubectl-edit\" using apps/v1:\n- .spec.template";
+        assert!(!is_legacy_conflict(&conflicting_managers(message)));
+        assert!(!is_legacy_conflict(&[]));
+
+        let mut live = ReplicaSet::default();
+        assert!(takeover_pending(&live));
+        live.annotations_mut()
+            .insert(SERVER_SIDE_APPLY_ANNOTATION.to_string(), "true".to_string());
+        assert!(!takeover_pending(&live));
+    }
+
+    #[test]
+    fn test_conflicting_managers_unknown_format() {
+        assert!(conflicting_managers("something went wrong").is_empty());
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use kube_runtime::reflector::{ObjectRef, Store};
+use serde::Serialize;
+
+use crate::crds::crd::Moodle;
+
+/// Per-Moodle exponential requeue delay after failed reconciles
+#[derive(Debug, Clone)]
+pub struct ErrorBackoff {
+    base: Duration,
+    max: Duration,
+    failures: Arc<Mutex<HashMap<ObjectRef<Moodle>, u32>>>,
+    stores: Arc<RwLock<Vec<Store<Moodle>>>>,
+}
+
+impl ErrorBackoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            failures: Arc::default(),
+            stores: Arc::default(),
+        }
+    }
+
+    /// Forget failures of Moodle objects that are no longer in these controller caches
+    pub fn watch_stores(&self, stores: Vec<Store<Moodle>>) {
+        *self.stores.write().unwrap() = stores;
+    }
+
+    /// Record a failure for `moodle` and return how long to wait before retrying
+    pub fn next_delay(&self, moodle: &ObjectRef<Moodle>) -> Duration {
+        let mut failures = self.failures.lock().unwrap();
+        self.prune(&mut failures);
+        let count = failures.entry(moodle.clone()).or_insert(0);
+        *count += 1;
+        self.delay(*count)
+    }
+
+    /// Forget previous failures of `moodle` after a successful reconcile
+    pub fn reset(&self, moodle: &ObjectRef<Moodle>) {
+        let mut failures = self.failures.lock().unwrap();
+        failures.remove(moodle);
+        self.prune(&mut failures);
+    }
+
+    /// Consecutive failures of `moodle` and the delay before its next retry, if it is failing
+    pub fn state(&self, moodle: &ObjectRef<Moodle>) -> Option<BackoffState> {
+        let failures = *self.failures.lock().unwrap().get(moodle)?;
+        Some(BackoffState {
+            failures,
+            delay_secs: self.delay(failures).as_secs(),
+        })
+    }
+
+    /// Drop failures of Moodle objects deleted while failing
+    fn prune(&self, failures: &mut HashMap<ObjectRef<Moodle>, u32>) {
+        let stores = self.stores.read().unwrap();
+        if !stores.is_empty() && failures.len() > stores.iter().map(Store::len).sum() {
+            failures.retain(|key, _| stores.iter().any(|store| store.get(key).is_some()));
+        }
+    }
+
+    /// Delay after `failures` consecutive failures, doubling from `base` up to `max`
+    fn delay(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.base.saturating_mul(factor).min(self.max)
+    }
+}
+
+/// Backoff of one Moodle object, as reported on `/api/v1/moodles`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BackoffState {
+    pub failures: u32,
+    pub delay_secs: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_grows_until_reset() {
+        let backoff = ErrorBackoff::new(Duration::from_secs(10), Duration::from_secs(60));
+        let moodle = ObjectRef::new("site").within("default");
+
+        assert_eq!(backoff.next_delay(&moodle), Duration::from_secs(10));
+        assert_eq!(backoff.next_delay(&moodle), Duration::from_secs(20));
+        assert_eq!(backoff.next_delay(&moodle), Duration::from_secs(40));
+        assert_eq!(backoff.next_delay(&moodle), Duration::from_secs(60));
+
+        assert_eq!(
+            backoff.state(&moodle),
+            Some(BackoffState {
+                failures: 4,
+                delay_secs: 60
+            })
+        );
+
+        backoff.reset(&moodle);
+        assert_eq!(backoff.state(&moodle), None);
+        assert_eq!(backoff.next_delay(&moodle), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_deleted_objects_are_pruned() {
+        let backoff = ErrorBackoff::new(Duration::from_secs(10), Duration::from_secs(60));
+        let writer = kube_runtime::reflector::store::Writer::<Moodle>::default();
+        backoff.watch_stores(vec![writer.as_reader()]);
+
+        let deleted = ObjectRef::new("deleted").within("default");
+        backoff.next_delay(&deleted);
+        backoff.reset(&ObjectRef::new("other").within("default"));
+        assert_eq!(backoff.state(&deleted), None);
+    }
+}
@@ -1,37 +1,165 @@
 use anyhow::Result;
-use futures::StreamExt;
-use kube::{Api, Client, ResourceExt};
-use kube_runtime::{controller, Controller};
+use futures::{future, StreamExt};
+use k8s_openapi::{
+    api::{
+        apps::v1::ReplicaSet,
+        core::v1::{ConfigMap, Secret},
+    },
+    NamespaceResourceScope,
+};
+use kube::{core::PartialObjectMeta, Api, Client, Resource, ResourceExt};
+use kube_runtime::{
+    controller,
+    reflector::{ObjectRef, Store},
+    watcher::{self, watcher},
+    Controller, WatchStreamExt,
+};
 use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info};
 
-use crate::{crds::crd::Moodle, error::Error, reconciller::reconcille_moodle::reconcile, Data};
-
-pub async fn controller_moodle_cluster(client: &Client) -> Result<()> {
-    let moodles = Api::all(client.clone());
-
-    Controller::new(moodles, Default::default())
-        .run(
-            reconcile,
-            error_policy,
-            Arc::new(Data {
-                client: client.clone(),
-            }),
-        )
-        .for_each(|res| async move {
-            match res {
-                Ok((obj_ref, _action)) => info!("Reconciled {:?}", obj_ref.name),
-                Err(e) => error!("Reconcile failed: {:?}", e),
-            }
+use crate::{
+    crds::crd::Moodle,
+    error::Error,
+    reconciller::{
+        reconcille_moodle::reconcile,
+        references::{config_map_owners, secret_owners},
+    },
+    Data,
+};
+
+/// Run the Moodle controller until `shutdown` is cancelled.
+/// - Watches `WATCH_NAMESPACES` (or the whole cluster) filtered by `MOODLE_LABEL_SELECTOR`.
+/// - In-flight reconciles are allowed to finish before returning.
+pub async fn controller_moodle_cluster(ctx: Arc<Data>, shutdown: CancellationToken) -> Result<()> {
+    let config = &ctx.config;
+    let client = &ctx.client;
+
+    let watcher_config = match &config.moodle_label_selector {
+        Some(selector) => watcher::Config::default().labels(selector),
+        None => watcher::Config::default(),
+    };
+
+    let apis: Vec<(Option<String>, Api<Moodle>)> = if config.watch_namespaces.is_empty() {
+        info!("Watching Moodle objects in all namespaces");
+        vec![(None, Api::all(client.clone()))]
+    } else {
+        info!(
+            "Watching Moodle objects in namespaces: {}",
+            config.watch_namespaces.join(", ")
+        );
+        config
+            .watch_namespaces
+            .iter()
+            .map(|ns| (Some(ns.clone()), Api::namespaced(client.clone(), ns)))
+            .collect()
+    };
+
+    // Only labeled Secrets and ConfigMaps are streamed, unless the selector is cleared
+    let reference_config = match &config.reference_label_selector {
+        Some(selector) => watcher::Config::default().labels(selector),
+        None => watcher::Config::default(),
+    };
+
+    // Concurrency is capped across controllers by `ReconcileLimit` in the reconciler
+    let controller_config = controller::Config::default().debounce(config.reconcile_debounce);
+
+    // One controller per watched namespace, all sharing the same context.
+    // Status changes of owned ReplicaSets requeue their Moodle, so the phase follows rollouts.
+    // Changed Secrets and ConfigMaps requeue the Moodle objects reading them. Only their metadata
+    // is watched, which changes with every update, so other contents are never streamed.
+    let controllers: Vec<_> = apis
+        .into_iter()
+        .map(|(namespace, moodles)| {
+            let controller = Controller::new(moodles, watcher_config.clone());
+            let (secret_store, config_map_store) = (controller.store(), controller.store());
+            let ns = namespace.as_deref();
+            controller
+                .with_config(controller_config.clone())
+                .owns(
+                    scoped_api::<ReplicaSet>(client, ns),
+                    watcher::Config::default(),
+                )
+                .watches_stream(
+                    watcher(
+                        scoped_api::<PartialObjectMeta<Secret>>(client, ns),
+                        reference_config.clone(),
+                    )
+                    .touched_objects(),
+                    move |secret| secret_owners(&secret_store, &secret),
+                )
+                .watches_stream(
+                    watcher(
+                        scoped_api::<PartialObjectMeta<ConfigMap>>(client, ns),
+                        reference_config.clone(),
+                    )
+                    .touched_objects(),
+                    move |config_map| config_map_owners(&config_map_store, &config_map),
+                )
+                .reconcile_on(ctx.trigger.subscribe(namespace))
+                .graceful_shutdown_on(shutdown.clone().cancelled_owned())
         })
-        .await;
+        .collect();
+    let stores: Vec<Store<Moodle>> = controllers.iter().map(Controller::store).collect();
+    ctx.metrics.watch_stores(stores.clone());
+    ctx.backoff.watch_stores(stores.clone());
+    ctx.state.started(stores.clone());
+
+    // Report readiness once the initial list of every watched namespace is cached
+    let synced = async {
+        if future::try_join_all(stores.iter().map(Store::wait_until_ready))
+            .await
+            .is_ok()
+        {
+            info!("Moodle watch caches synced");
+            ctx.state.synced();
+        }
+    };
+
+    let runs = controllers.into_iter().map(|controller| {
+        controller
+            .run(reconcile, error_policy, ctx.clone())
+            .for_each(|res| async move {
+                match res {
+                    Ok((obj_ref, _action)) => info!("Reconciled {:?}", obj_ref.name),
+                    Err(e) => error!("Reconcile failed: {:?}", e),
+                }
+            })
+    });
+    future::join(future::join_all(runs), synced).await;
+    ctx.state.stopped();
+
+    if shutdown.is_cancelled() {
+        info!("Moodle controller stopped");
+        return Ok(());
+    }
 
     Err(anyhow::anyhow!(
         "controller_moodle_cluster exited unexpectedly"
     ))
 }
 
-fn error_policy(moodle: Arc<Moodle>, err: &Error, _ctx: Arc<Data>) -> controller::Action {
-    error!("Error reconciling Moodle '{}': {}", moodle.name_any(), err);
-    controller::Action::requeue(std::time::Duration::from_secs(10))
+fn error_policy(moodle: Arc<Moodle>, err: &Error, ctx: Arc<Data>) -> controller::Action {
+    let delay = ctx
+        .backoff
+        .next_delay(&ObjectRef::from_obj(moodle.as_ref()));
+    ctx.metrics.record_failure(err.kind());
+    error!(
+        "Error reconciling Moodle '{}': {}. Retrying in {:?}",
+        moodle.name_any(),
+        err,
+        delay
+    );
+    controller::Action::requeue(delay)
+}
+
+/// Api for `K` in the watched `namespace`, or across the cluster without one
+fn scoped_api<K>(client: &Client, namespace: Option<&str>) -> Api<K>
+where
+    K: Resource<Scope = NamespaceResourceScope, DynamicType = ()>,
+{
+    match namespace {
+        Some(ns) => Api::namespaced(client.clone(), ns),
+        None => Api::all(client.clone()),
+    }
 }
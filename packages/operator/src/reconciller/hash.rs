@@ -0,0 +1,25 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Hex encoded SHA-256 of the JSON form of `value`, stable across operator restarts
+pub fn json_hash<T: Serialize>(value: &T) -> String {
+    let json = serde_json::to_vec(value).expect("serializing to JSON cannot fail");
+    Sha256::digest(json)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_hash_is_stable() {
+        let hash = json_hash(&json!({ "replicas": 2 }));
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, json_hash(&json!({ "replicas": 2 })));
+        assert_ne!(hash, json_hash(&json!({ "replicas": 3 })));
+    }
+}
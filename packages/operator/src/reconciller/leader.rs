@@ -0,0 +1,269 @@
+use anyhow::{bail, Result};
+use futures::future;
+use k8s_openapi::{
+    api::coordination::v1::{Lease, LeaseSpec},
+    apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta},
+    jiff::{SignedDuration, Timestamp},
+};
+use kube::{api::PostParams, Api, Client};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    config::LeaderElectionConfig, reconciller::controller::controller_moodle_cluster, Data,
+};
+
+/// Coordination Lease held by the one replica running the controllers
+#[derive(Clone)]
+pub struct LeaderLease {
+    api: Api<Lease>,
+    config: LeaderElectionConfig,
+}
+
+impl LeaderLease {
+    pub fn new(client: Client, config: LeaderElectionConfig) -> Self {
+        Self {
+            api: Api::namespaced(client, &config.namespace),
+            config,
+        }
+    }
+
+    /// Wait until this replica holds the lease, false if `shutdown` was cancelled first
+    async fn acquire(&self, shutdown: &CancellationToken) -> bool {
+        loop {
+            match self.try_hold().await {
+                Ok(true) => return true,
+                Ok(false) => {}
+                Err(e) => warn!(
+                    "Failed to acquire leader lease {}: {e}",
+                    self.config.lease_name
+                ),
+            }
+            tokio::select! {
+                _ = shutdown.cancelled() => return false,
+                _ = tokio::time::sleep(self.retry_period()) => {}
+            }
+        }
+    }
+
+    /// Renew the lease until `leading` is cancelled, cancelling it when the lease is lost
+    async fn renew(&self, leading: &CancellationToken) {
+        // Stop leading well before another replica may consider the lease expired
+        let renew_deadline = self.config.lease_duration * 2 / 3;
+        let mut renewed = Instant::now();
+        loop {
+            tokio::select! {
+                _ = leading.cancelled() => return,
+                _ = tokio::time::sleep(self.retry_period()) => {}
+            }
+            match self.try_hold().await {
+                Ok(true) => renewed = Instant::now(),
+                Ok(false) => {
+                    warn!("Leader lease {} was taken over", self.config.lease_name);
+                    break;
+                }
+                Err(e) if renewed.elapsed() < renew_deadline => {
+                    warn!(
+                        "Failed to renew leader lease {}: {e}",
+                        self.config.lease_name
+                    )
+                }
+                Err(e) => {
+                    warn!(
+                        "Leader lease {} not renewed within {renew_deadline:?}: {e}",
+                        self.config.lease_name
+                    );
+                    break;
+                }
+            }
+        }
+        leading.cancel();
+    }
+
+    /// Give the lease up so a standby replica takes over without waiting for it to expire
+    async fn release(&self) {
+        let name = &self.config.lease_name;
+        let released = async {
+            let mut lease = self.api.get(name).await?;
+            let Some(spec) = lease.spec.as_mut() else {
+                return Ok(());
+            };
+            if spec.holder_identity.as_deref() == Some(self.config.identity.as_str()) {
+                spec.holder_identity = None;
+                self.api
+                    .replace(name, &PostParams::default(), &lease)
+                    .await?;
+                info!("Released leader lease {name}");
+            }
+            Ok::<_, kube::Error>(())
+        };
+        if let Err(e) = released.await {
+            warn!("Failed to release leader lease {name}: {e}");
+        }
+    }
+
+    /// Take or renew the lease once, true while this replica holds it
+    #[instrument(
+        name = "kube.lease",
+        skip_all,
+        fields(otel.kind = "client", k8s.kind = "Lease", k8s.name = %self.config.lease_name)
+    )]
+    async fn try_hold(&self) -> Result<bool, kube::Error> {
+        let name = &self.config.lease_name;
+        let (identity, duration) = (&self.config.identity, self.config.lease_duration);
+        let now = Timestamp::now();
+
+        let written = match self.api.get_opt(name).await? {
+            None => {
+                let lease = Lease {
+                    metadata: ObjectMeta {
+                        name: Some(name.clone()),
+                        ..Default::default()
+                    },
+                    spec: claim(None, identity, duration, now),
+                };
+                self.api.create(&PostParams::default(), &lease).await
+            }
+            Some(mut lease) => match claim(lease.spec.as_ref(), identity, duration, now) {
+                // The resourceVersion of the read lease makes the update fail if it changed since
+                Some(spec) => {
+                    lease.spec = Some(spec);
+                    self.api.replace(name, &PostParams::default(), &lease).await
+                }
+                None => return Ok(false),
+            },
+        };
+
+        match written {
+            Ok(_) => Ok(true),
+            // Another replica created or updated the lease first
+            Err(kube::Error::Api(status)) if status.code == 409 => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn retry_period(&self) -> Duration {
+        self.config.lease_duration / 3
+    }
+}
+
+/// Run the Moodle controller, only while this replica holds `lease` when leader election is on.
+/// - Losing the lease stops the controllers and fails the task, which restarts as a standby.
+pub async fn lead(
+    ctx: Arc<Data>,
+    lease: Option<LeaderLease>,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let Some(lease) = lease else {
+        info!("Starting Moodle controller");
+        ctx.state.set_leader(true);
+        return controller_moodle_cluster(ctx, shutdown).await;
+    };
+
+    info!(
+        "Waiting for leader lease {}/{} as {}",
+        lease.config.namespace, lease.config.lease_name, lease.config.identity
+    );
+    if !lease.acquire(&shutdown).await {
+        return Ok(());
+    }
+    info!("Acquired leader lease, starting Moodle controller");
+    ctx.state.set_leader(true);
+
+    let leading = shutdown.child_token();
+    let controller = async {
+        let result = controller_moodle_cluster(ctx.clone(), leading.clone()).await;
+        leading.cancel();
+        result
+    };
+    let (result, ()) = future::join(controller, lease.renew(&leading)).await;
+    ctx.state.set_leader(false);
+    lease.release().await;
+
+    if result.is_ok() && !shutdown.is_cancelled() {
+        bail!("lost leader lease {}", lease.config.lease_name);
+    }
+    result
+}
+
+/// Lease spec once `identity` took or renewed it at `now`, None while another holder's lease is valid
+fn claim(
+    current: Option<&LeaseSpec>,
+    identity: &str,
+    duration: Duration,
+    now: Timestamp,
+) -> Option<LeaseSpec> {
+    let current = current.cloned().unwrap_or_default();
+    let holder = current
+        .holder_identity
+        .as_deref()
+        .filter(|holder| !holder.is_empty());
+    let renewing = holder == Some(identity);
+
+    if holder.is_some() && !renewing {
+        let held_for = current.lease_duration_seconds.map_or(
+            SignedDuration::from_secs(duration.as_secs() as i64),
+            |secs| SignedDuration::from_secs(secs.into()),
+        );
+        let valid = current
+            .renew_time
+            .as_ref()
+            .is_some_and(|renewed| now.duration_since(renewed.0) < held_for);
+        if valid {
+            return None;
+        }
+    }
+
+    Some(LeaseSpec {
+        holder_identity: Some(identity.to_string()),
+        lease_duration_seconds: Some(duration.as_secs() as i32),
+        acquire_time: match renewing {
+            true => current.acquire_time.clone(),
+            false => Some(MicroTime(now)),
+        },
+        renew_time: Some(MicroTime(now)),
+        lease_transitions: match (renewing, holder) {
+            (false, Some(_)) => Some(current.lease_transitions.unwrap_or(0) + 1),
+            _ => current.lease_transitions.or(Some(0)),
+        },
+        ..current
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claim_waits_for_expiry() {
+        let duration = Duration::from_secs(15);
+        let start = Timestamp::now();
+
+        let ours = claim(None, "a", duration, start).unwrap();
+        assert_eq!(ours.holder_identity.as_deref(), Some("a"));
+        assert_eq!(ours.lease_transitions, Some(0));
+
+        // Renewing keeps the acquire time, another replica waits for the lease to expire
+        let later = start + SignedDuration::from_secs(5);
+        let renewed = claim(Some(&ours), "a", duration, later).unwrap();
+        assert_eq!(renewed.acquire_time, ours.acquire_time);
+        assert_eq!(renewed.renew_time, Some(MicroTime(later)));
+        assert_eq!(claim(Some(&renewed), "b", duration, later), None);
+
+        let expired = later + SignedDuration::from_secs(15);
+        let taken = claim(Some(&renewed), "b", duration, expired).unwrap();
+        assert_eq!(taken.holder_identity.as_deref(), Some("b"));
+        assert_eq!(taken.lease_transitions, Some(1));
+
+        // A released lease is free right away
+        let released = LeaseSpec {
+            holder_identity: None,
+            ..taken
+        };
+        assert!(claim(Some(&released), "a", duration, expired).is_some());
+    }
+}
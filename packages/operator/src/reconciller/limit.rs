@@ -0,0 +1,48 @@
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Caps the reconciles running at once, shared by the controllers of every watched namespace
+#[derive(Clone, Default)]
+pub struct ReconcileLimit {
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl ReconcileLimit {
+    /// At most `concurrency` reconciles at once, unbounded for 0
+    pub fn new(concurrency: u16) -> Self {
+        Self {
+            permits: (concurrency > 0).then(|| Arc::new(Semaphore::new(concurrency.into()))),
+        }
+    }
+
+    /// Wait for a free slot, held until the returned permit is dropped
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        let permits = self.permits.clone()?;
+        // The semaphore is never closed
+        permits.acquire_owned().await.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_limit_is_shared_by_clones() {
+        let limit = ReconcileLimit::new(1);
+        let other_namespace = limit.clone();
+
+        let permit = limit.acquire().await;
+        assert!(permit.is_some());
+        let waiting = tokio::time::timeout(
+            std::time::Duration::from_millis(10),
+            other_namespace.acquire(),
+        );
+        assert!(waiting.await.is_err());
+
+        drop(permit);
+        assert!(other_namespace.acquire().await.is_some());
+        assert!(ReconcileLimit::new(0).acquire().await.is_none());
+    }
+}
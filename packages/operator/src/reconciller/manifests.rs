@@ -0,0 +1,213 @@
+use k8s_openapi::api::apps::v1::{ReplicaSet, ReplicaSetSpec};
+use k8s_openapi::api::core::v1::{
+    ConfigMapEnvSource, Container, EnvFromSource, EnvVar, EnvVarSource,
+    PersistentVolumeClaimVolumeSource, PodSpec, PodTemplateSpec, SecretKeySelector, Volume,
+    VolumeMount,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use kube::{Resource, ResourceExt};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{
+    crds::crd::Moodle,
+    reconciller::{apply::SERVER_SIDE_APPLY_ANNOTATION, references::CONFIG_HASH_ANNOTATION},
+};
+
+/// Every child object desired for a Moodle site, built without calling the API server
+#[derive(Debug, Clone)]
+pub struct Manifests {
+    pub replicaset: ReplicaSet,
+}
+
+impl Manifests {
+    /// Desired children of `moodle`, whose spec must already have the defaults applied.
+    /// - `config_hash` covers the referenced Secrets and ConfigMaps, unknown when rendering offline.
+    pub fn build(moodle: &Moodle, config_hash: Option<&str>) -> Self {
+        Self {
+            replicaset: replicaset(moodle, config_hash),
+        }
+    }
+
+    /// The children as a multi-document YAML stream, in apply order
+    pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
+        let mut yaml = String::new();
+        document(&mut yaml, &self.replicaset)?;
+        Ok(yaml)
+    }
+}
+
+fn document<K: Serialize>(yaml: &mut String, object: &K) -> Result<(), serde_yaml::Error> {
+    yaml.push_str("---\n");
+    yaml.push_str(&serde_yaml::to_string(object)?);
+    Ok(())
+}
+
+/// ReplicaSet running the Moodle pods
+fn replicaset(moodle: &Moodle, config_hash: Option<&str>) -> ReplicaSet {
+    let labels = BTreeMap::from([("app".to_string(), moodle.name_any())]);
+
+    let pvc_mount = VolumeMount {
+        name: "moodle-data".to_string(),
+        mount_path: "/bitnami/moodle".to_string(),
+        ..Default::default()
+    };
+
+    let container = Container {
+        name: "moodle".to_string(),
+        image: Some(moodle.spec.image.clone()),
+        volume_mounts: Some(vec![pvc_mount]),
+        env: Some(vec![
+            EnvVar {
+                name: "MOODLE_DATABASE_HOST".to_string(),
+                value: Some(moodle.spec.database.host.clone()),
+                ..Default::default()
+            },
+            EnvVar {
+                name: "MOODLE_DATABASE_TYPE".to_string(),
+                value: Some(moodle.spec.database.db_type.to_string()),
+                ..Default::default()
+            },
+            EnvVar {
+                name: "MOODLE_DATABASE_PORT_NUMBER".to_string(),
+                value: Some(moodle.spec.database.port.to_string()),
+                ..Default::default()
+            },
+            EnvVar {
+                name: "MOODLE_DATABASE_USER".to_string(),
+                value: Some(moodle.spec.database.user.clone()),
+                ..Default::default()
+            },
+            database_password(moodle),
+            EnvVar {
+                name: "MOODLE_DATABASE_NAME".to_string(),
+                value: Some(moodle.spec.database.name.clone()),
+                ..Default::default()
+            },
+        ]),
+        env_from: moodle.spec.env_config_map.as_ref().map(|name| {
+            vec![EnvFromSource {
+                config_map_ref: Some(ConfigMapEnvSource {
+                    name: name.clone(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }]
+        }),
+        ..Default::default()
+    };
+
+    let volume = Volume {
+        name: "moodle-data".to_string(),
+        persistent_volume_claim: Some(PersistentVolumeClaimVolumeSource {
+            claim_name: moodle.spec.pvc_name.clone(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let pod_template = PodTemplateSpec {
+        metadata: Some(kube::core::ObjectMeta {
+            labels: Some(labels.clone()),
+            annotations: config_hash.map(|hash| {
+                BTreeMap::from([(CONFIG_HASH_ANNOTATION.to_string(), hash.to_string())])
+            }),
+            ..Default::default()
+        }),
+        spec: Some(PodSpec {
+            containers: vec![container],
+            volumes: Some(vec![volume]),
+            ..Default::default()
+        }),
+    };
+
+    let rs_spec = ReplicaSetSpec {
+        replicas: Some(moodle.spec.replicas),
+        selector: LabelSelector {
+            match_labels: Some(labels.clone()),
+            ..Default::default()
+        },
+        template: Some(pod_template),
+        ..Default::default()
+    };
+
+    ReplicaSet {
+        metadata: kube::core::ObjectMeta {
+            name: Some(moodle.name_any()),
+            namespace: moodle.namespace(),
+            // Left out when rendering objects that were never stored, as they have no uid
+            owner_references: moodle.controller_owner_ref(&()).map(|owner| vec![owner]),
+            labels: Some(labels),
+            annotations: Some(BTreeMap::from([(
+                SERVER_SIDE_APPLY_ANNOTATION.to_string(),
+                "true".to_string(),
+            )])),
+            ..Default::default()
+        },
+        spec: Some(rs_spec),
+        ..Default::default()
+    }
+}
+
+/// Password from the referenced Secret key when set, otherwise inline from the spec
+fn database_password(moodle: &Moodle) -> EnvVar {
+    let database = &moodle.spec.database;
+    EnvVar {
+        name: "MOODLE_DATABASE_PASSWORD".to_string(),
+        value: database
+            .password_secret_ref
+            .is_none()
+            .then(|| database.password.clone()),
+        value_from: database
+            .password_secret_ref
+            .as_ref()
+            .map(|secret| EnvVarSource {
+                secret_key_ref: Some(SecretKeySelector {
+                    name: secret.name.clone(),
+                    key: secret.key.clone(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_hash_annotates_pod_template() {
+        let moodle: Moodle = serde_json::from_value(serde_json::json!({
+            "apiVersion": "moodle.adorsys.com/v1",
+            "kind": "Moodle",
+            "metadata": { "name": "site", "namespace": "default" },
+            "spec": {
+                "image": "bitnami/moodle:5.0",
+                "pvcName": "moodle-data",
+                "database": {
+                    "host": "db", "port": 5432, "user": "moodle", "type": "pgsql", "name": "moodle",
+                    "passwordSecretRef": { "name": "moodle-db", "key": "password" }
+                }
+            }
+        }))
+        .unwrap();
+
+        let annotations = |manifests: Manifests| {
+            manifests
+                .replicaset
+                .spec
+                .and_then(|spec| spec.template)
+                .and_then(|template| template.metadata)
+                .and_then(|metadata| metadata.annotations)
+        };
+        assert_eq!(annotations(Manifests::build(&moodle, None)), None);
+        assert_eq!(
+            annotations(Manifests::build(&moodle, Some("abc"))),
+            Some(BTreeMap::from([(
+                CONFIG_HASH_ANNOTATION.to_string(),
+                "abc".to_string()
+            )]))
+        );
+    }
+}
@@ -1,3 +1,14 @@
+pub mod apply;
+pub mod backoff;
 pub mod controller;
 pub mod create_or_update_rs;
+pub mod hash;
+pub mod leader;
+pub mod limit;
+pub mod manifests;
 mod reconcille_moodle;
+pub mod references;
+pub mod rollout;
+pub mod state;
+mod status;
+pub mod trigger;
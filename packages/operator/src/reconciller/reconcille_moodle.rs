@@ -1,15 +1,63 @@
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use kube::{Resource, ResourceExt};
-use kube_runtime::controller::{self, Action};
-use tracing::info;
+use kube::{Api, Resource, ResourceExt};
+use kube_runtime::{
+    controller::{self, Action},
+    reflector::ObjectRef,
+};
+use tracing::{field, info, info_span, Instrument, Span};
 
 use crate::{
-    crds::crd::Moodle, error::Error, reconciller::create_or_update_rs::create_or_update_replicaset,
+    crds::crd::{Moodle, PAUSED_ANNOTATION, RECONCILE_AT_ANNOTATION},
+    error::Error,
+    reconciller::{
+        create_or_update_rs::create_or_update_replicaset,
+        references::{config_hash, References},
+        rollout::replace_outdated_pod,
+        state::ChildSummary,
+        status::{
+            condition, patch_status, set_condition, CONDITION_CONFLICT, CONDITION_PAUSED,
+            CONDITION_SPEC_VALID, PHASE_FAILED, PHASE_INVALID, PHASE_PROGRESSING, PHASE_RUNNING,
+        },
+    },
     Data,
 };
 
+/// Delay between pod replacements while rolling out new Secret or ConfigMap contents
+const ROLLOUT_REQUEUE: Duration = Duration::from_secs(10);
+
 pub async fn reconcile(moodle: Arc<Moodle>, ctx: Arc<Data>) -> Result<Action, Error> {
+    // Root span of the reconcile pass, `action` is filled in by the branch taken
+    let span = info_span!(
+        "reconcile",
+        moodle.name = %moodle.name_any(),
+        moodle.namespace = %moodle.namespace().unwrap_or_default(),
+        moodle.generation = moodle.meta().generation.unwrap_or_default(),
+        action = field::Empty,
+        otel.status_code = field::Empty,
+    );
+
+    // Shared by every namespace controller, so RECONCILE_CONCURRENCY is a process-wide cap
+    let mut slot = ctx.metrics.enqueue();
+    let _permit = ctx.limit.acquire().await;
+    slot.start();
+    let started = Instant::now();
+    let result = reconcile_moodle(&moodle, &ctx)
+        .instrument(span.clone())
+        .await;
+    let key = ObjectRef::from_obj(moodle.as_ref());
+    if let Err(e) = &result {
+        span.record("otel.status_code", "ERROR");
+        ctx.state.record_error(&key, e.to_string());
+    }
+    ctx.metrics
+        .record_reconcile(started.elapsed(), result.is_ok());
+    ctx.state.record_reconcile(&key);
+    result
+}
+
+async fn reconcile_moodle(moodle: &Moodle, ctx: &Data) -> Result<Action, Error> {
     let client = &ctx.client;
 
     if moodle.meta().deletion_timestamp.is_some() {
@@ -17,31 +65,160 @@ pub async fn reconcile(moodle: Arc<Moodle>, ctx: Arc<Data>) -> Result<Action, Er
             "Moodle {} is marked for deletion. Skipping reconciliation.",
             moodle.name_any()
         );
+        Span::current().record("action", "skip_deleted");
         return Ok(Action::await_change());
     }
 
+    let moodle_api: Api<Moodle> = Api::namespaced(client.clone(), &moodle.namespace().unwrap());
+    let mut status = moodle.status.clone().unwrap_or_default();
+
+    // A new reconcile-at value is a manual retry, so restart the error backoff
+    if let Some(requested_at) = moodle.annotations().get(RECONCILE_AT_ANNOTATION) {
+        if status.last_handled_reconcile_at.as_ref() != Some(requested_at) {
+            info!(
+                "Reconcile of Moodle {} requested at {requested_at}",
+                moodle.name_any()
+            );
+            ctx.backoff.reset(&ObjectRef::from_obj(moodle));
+            status.last_handled_reconcile_at = Some(requested_at.clone());
+        }
+    }
+
+    // Paused sites keep their children as they are, e.g. during manual incident work.
+    // Removing the annotation triggers a new reconcile.
+    if moodle.is_paused() {
+        info!(
+            "Moodle {} is paused by {PAUSED_ANNOTATION}. Skipping changes.",
+            moodle.name_any()
+        );
+        Span::current().record("action", "paused");
+        set_condition(
+            &mut status.conditions,
+            condition(
+                moodle,
+                CONDITION_PAUSED,
+                true,
+                "PausedByAnnotation",
+                &format!("{PAUSED_ANNOTATION} is \"true\", child resources are not updated"),
+            ),
+        );
+        patch_status(moodle, &moodle_api, &status).await?;
+        return Ok(Action::await_change());
+    }
+    set_condition(
+        &mut status.conditions,
+        condition(moodle, CONDITION_PAUSED, false, "Reconciling", ""),
+    );
+
+    // Fill fields the mutating webhook would have defaulted, in case it is not installed
+    let defaulted = ctx.config.spec_defaults.applied(moodle);
+
     // Validate the Moodle CRD
-    if let Err(validation_err) = moodle.spec.validate() {
+    if let Err(errors) = defaulted.spec.validate() {
         tracing::error!(
             "Invalid Moodle CRD {}: {}. Will requeue.",
             moodle.name_any(),
-            validation_err
+            errors
         );
-        return Ok(Action::requeue(std::time::Duration::from_secs(30)));
+        Span::current().record("action", "invalid_spec");
+        ctx.state
+            .record_invalid(&ObjectRef::from_obj(moodle), &errors);
+        status.phase = Some(PHASE_INVALID.to_string());
+        set_condition(
+            &mut status.conditions,
+            condition(
+                moodle,
+                CONDITION_SPEC_VALID,
+                false,
+                "ValidationFailed",
+                &errors.to_string(),
+            ),
+        );
+        patch_status(moodle, &moodle_api, &status).await?;
+        return Ok(Action::requeue(ctx.config.resync_interval));
     }
+    set_condition(
+        &mut status.conditions,
+        condition(moodle, CONDITION_SPEC_VALID, true, "Valid", ""),
+    );
+
+    // Rotated Secrets or ConfigMaps change the pod template through this hash
+    let references = References::of(&defaulted.spec);
+    let config_hash = config_hash(client, &moodle.namespace().unwrap(), &references).await?;
 
-    match create_or_update_replicaset(&moodle, client).await {
-        Ok(_) => {
+    match create_or_update_replicaset(&defaulted, config_hash.as_deref(), client).await {
+        Ok(replicaset) => {
             tracing::info!("Successfully created or updated ReplicaSet.");
+            Span::current().record("action", "applied");
+            let ready = replicaset
+                .status
+                .as_ref()
+                .and_then(|s| s.ready_replicas)
+                .unwrap_or(0);
+            ctx.state.record_applied(
+                moodle,
+                vec![ChildSummary {
+                    kind: "ReplicaSet".to_string(),
+                    name: replicaset.name_any(),
+                    replicas: replicaset.spec.as_ref().and_then(|spec| spec.replicas),
+                    ready_replicas: Some(ready),
+                }],
+            );
+            let phase = if ready >= moodle.spec.replicas {
+                PHASE_RUNNING
+            } else {
+                PHASE_PROGRESSING
+            };
+
+            status.ready_replicas = Some(ready);
+            status.phase = Some(phase.to_string());
+            set_condition(
+                &mut status.conditions,
+                condition(moodle, CONDITION_CONFLICT, false, "NoConflict", ""),
+            );
+
+            // Check again soon while pods are replaced one at a time
+            if replace_outdated_pod(&replicaset, client).await? {
+                Span::current().record("action", "rollout");
+                status.phase = Some(PHASE_PROGRESSING.to_string());
+                patch_status(moodle, &moodle_api, &status).await?;
+                return Ok(Action::requeue(ROLLOUT_REQUEUE));
+            }
+            patch_status(moodle, &moodle_api, &status).await?;
         }
         Err(e) => {
             tracing::error!("Failed to create or update ReplicaSet: {}", e);
+            Span::current().record("action", "apply_failed");
+            status.phase = Some(PHASE_FAILED.to_string());
+            if let Error::ApplyConflict {
+                kind,
+                name,
+                managers,
+                ..
+            } = &e
+            {
+                let message = format!(
+                    "{kind} {name} has fields managed by {}",
+                    managers.join(", ")
+                );
+                set_condition(
+                    &mut status.conditions,
+                    condition(
+                        moodle,
+                        CONDITION_CONFLICT,
+                        true,
+                        "FieldManagerConflict",
+                        &message,
+                    ),
+                );
+            }
+            patch_status(moodle, &moodle_api, &status).await?;
             return Err(e);
         }
     }
 
-    //  requeue after 30s
-    Ok(controller::Action::requeue(std::time::Duration::from_secs(
-        30,
-    )))
+    ctx.backoff.reset(&ObjectRef::from_obj(moodle));
+
+    // requeue after the resync interval
+    Ok(controller::Action::requeue(ctx.config.resync_interval))
 }
@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use kube::{core::PartialObjectMeta, Api, Client, Resource, ResourceExt};
+use kube_runtime::reflector::{ObjectRef, Store};
+use serde_json::{json, Value};
+use tracing::instrument;
+
+use crate::{
+    crds::crd::{Moodle, MoodleSpec},
+    error::Error,
+    reconciller::hash::json_hash,
+};
+
+/// Pod-template annotation holding the hash of every referenced Secret and ConfigMap.
+/// - A new value changes the template, so pods are replaced with the new contents.
+pub const CONFIG_HASH_ANNOTATION: &str = "moodle.adorsys.com/config-hash";
+
+/// Label selecting the Secrets and ConfigMaps whose changes are watched, by default.
+/// - Changes to unlabeled ones are only picked up by the next resync.
+pub const WATCH_LABEL_SELECTOR: &str = "moodle.adorsys.com/watch=true";
+
+/// Secrets and ConfigMaps a Moodle spec reads, all in the namespace of the Moodle object
+#[derive(Debug, Default, PartialEq)]
+pub struct References {
+    pub secrets: Vec<String>,
+    pub config_maps: Vec<String>,
+}
+
+impl References {
+    pub fn of(spec: &MoodleSpec) -> Self {
+        Self {
+            secrets: spec
+                .database
+                .password_secret_ref
+                .iter()
+                .map(|secret| secret.name.clone())
+                .collect(),
+            config_maps: spec.env_config_map.iter().cloned().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.secrets.is_empty() && self.config_maps.is_empty()
+    }
+}
+
+/// Hash of the contents of every referenced object, None when nothing is referenced.
+/// - Missing objects are hashed as absent, so creating them later also rolls the pods.
+pub async fn config_hash(
+    client: &Client,
+    namespace: &str,
+    references: &References,
+) -> Result<Option<String>, Error> {
+    if references.is_empty() {
+        return Ok(None);
+    }
+
+    let mut contents: BTreeMap<String, Value> = BTreeMap::new();
+    let secrets: Api<Secret> = Api::namespaced(client.clone(), namespace);
+    for name in &references.secrets {
+        let secret = read(&secrets, name).await?;
+        // `stringData` is write-only, the API server merges it into `data`
+        let data = secret.map(|secret| json!(secret.data));
+        contents.insert(format!("Secret/{name}"), data.unwrap_or_default());
+    }
+    let config_maps: Api<ConfigMap> = Api::namespaced(client.clone(), namespace);
+    for name in &references.config_maps {
+        let config_map = read(&config_maps, name).await?;
+        let data = config_map.map(|config_map| json!([config_map.data, config_map.binary_data]));
+        contents.insert(format!("ConfigMap/{name}"), data.unwrap_or_default());
+    }
+    Ok(Some(json_hash(&contents)))
+}
+
+#[instrument(
+    name = "kube.get",
+    skip_all,
+    fields(otel.kind = "client", k8s.kind = %K::kind(&()), k8s.name = %name)
+)]
+async fn read<K>(api: &Api<K>, name: &str) -> Result<Option<K>, Error>
+where
+    K: Resource<DynamicType = ()> + Clone + serde::de::DeserializeOwned + std::fmt::Debug,
+{
+    api.get_opt(name).await.map_err(|source| Error::ReadFailed {
+        kind: K::kind(&()).to_string(),
+        name: name.to_string(),
+        source,
+    })
+}
+
+/// Cached Moodle objects reading the Secret `object`, to reconcile when it changes
+pub fn secret_owners(
+    store: &Store<Moodle>,
+    object: &PartialObjectMeta<Secret>,
+) -> Vec<ObjectRef<Moodle>> {
+    owners(store, object, |references| &references.secrets)
+}
+
+/// Cached Moodle objects reading the ConfigMap `object`, to reconcile when it changes
+pub fn config_map_owners(
+    store: &Store<Moodle>,
+    object: &PartialObjectMeta<ConfigMap>,
+) -> Vec<ObjectRef<Moodle>> {
+    owners(store, object, |references| &references.config_maps)
+}
+
+fn owners<K: Resource>(
+    store: &Store<Moodle>,
+    object: &K,
+    names: fn(&References) -> &Vec<String>,
+) -> Vec<ObjectRef<Moodle>> {
+    let namespace = object.namespace();
+    let name = object.name_any();
+    store
+        .state()
+        .iter()
+        .filter(|moodle| moodle.namespace() == namespace)
+        .filter(|moodle| names(&References::of(&moodle.spec)).contains(&name))
+        .map(|moodle| ObjectRef::from_obj(moodle.as_ref()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use kube::core::PartialObjectMetaExt;
+    use kube_runtime::{reflector::store::Writer, watcher::Event};
+
+    fn moodle(namespace: &str, name: &str, secret: Option<&str>) -> Moodle {
+        serde_json::from_value(json!({
+            "apiVersion": "moodle.adorsys.com/v1",
+            "kind": "Moodle",
+            "metadata": { "name": name, "namespace": namespace },
+            "spec": {
+                "pvcName": "moodle-data",
+                "envConfigMap": "moodle-env",
+                "database": {
+                    "host": "db", "user": "moodle", "type": "pgsql", "name": "moodle",
+                    "passwordSecretRef": secret.map(|name| json!({ "name": name, "key": "password" }))
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_owners_of_changed_objects() {
+        let mut writer = Writer::<Moodle>::default();
+        writer.apply_watcher_event(&Event::Apply(moodle("default", "a", Some("db"))));
+        writer.apply_watcher_event(&Event::Apply(moodle("default", "b", None)));
+        writer.apply_watcher_event(&Event::Apply(moodle("other", "c", Some("db"))));
+        let store = writer.as_reader();
+
+        let metadata = |name: &str| ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some("default".to_string()),
+            ..ObjectMeta::default()
+        };
+        let secret = metadata("db").into_response_partial::<Secret>();
+        assert_eq!(
+            secret_owners(&store, &secret),
+            [ObjectRef::new("a").within("default")]
+        );
+
+        let config_map = metadata("moodle-env").into_response_partial::<ConfigMap>();
+        let mut owners = config_map_owners(&store, &config_map);
+        owners.sort_by_key(|owner| owner.name.clone());
+        assert_eq!(
+            owners,
+            [
+                ObjectRef::new("a").within("default"),
+                ObjectRef::new("b").within("default")
+            ]
+        );
+    }
+}
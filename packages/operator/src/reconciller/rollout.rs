@@ -0,0 +1,120 @@
+use k8s_openapi::api::{apps::v1::ReplicaSet, core::v1::Pod};
+use kube::{
+    api::{DeleteParams, ListParams},
+    Api, Client, ResourceExt,
+};
+use tracing::{info, info_span, Instrument};
+
+use crate::{error::Error, reconciller::references::CONFIG_HASH_ANNOTATION};
+
+/// Whether pods selected by the desired `replicaset` still run with outdated Secret or ConfigMap
+/// contents. It is then applied with one surge replica, so a pod with the new contents is ready
+/// before any outdated one is deleted, and a single replica site stays up.
+pub async fn rollout_pending(replicaset: &ReplicaSet, client: &Client) -> Result<bool, Error> {
+    Ok(!outdated_pods(replicaset, client).await?.is_empty())
+}
+
+/// Add the surge replica kept while outdated pods are replaced
+pub fn surge(replicaset: &mut ReplicaSet) {
+    if let Some(spec) = replicaset.spec.as_mut() {
+        spec.replicas = Some(spec.replicas.unwrap_or(1) + 1);
+    }
+}
+
+/// Replace one pod of `replicaset` still running with outdated Secret or ConfigMap contents.
+/// - A ReplicaSet never replaces its pods when the template changes, so the operator does.
+/// - Only one pod is deleted per call, and only while every replica, the surge one included,
+///   is ready.
+///
+/// Returns whether a pod was deleted and the rollout is still in progress.
+pub async fn replace_outdated_pod(replicaset: &ReplicaSet, client: &Client) -> Result<bool, Error> {
+    let replicas = replicaset
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.replicas)
+        .unwrap_or(1);
+    let ready = replicaset
+        .status
+        .as_ref()
+        .and_then(|status| status.ready_replicas)
+        .unwrap_or(0);
+    if ready < replicas {
+        return Ok(false);
+    }
+
+    let Some(pod) = outdated_pods(replicaset, client).await?.into_iter().next() else {
+        return Ok(false);
+    };
+    let name = pod.name_any();
+    info!("Replacing Pod {name}, its Secret or ConfigMap contents are outdated");
+    let pods: Api<Pod> =
+        Api::namespaced(client.clone(), &replicaset.namespace().unwrap_or_default());
+    pods.delete(&name, &DeleteParams::default())
+        .instrument(info_span!(
+            "kube.delete",
+            otel.kind = "client",
+            k8s.kind = "Pod",
+            k8s.name = %name
+        ))
+        .await
+        .map_err(|source| Error::PodDeleteFailed {
+            name: name.clone(),
+            source,
+        })?;
+    Ok(true)
+}
+
+/// Running pods selected by `replicaset` whose config hash differs from its pod template.
+/// - Once the ReplicaSet exists, only the pods it owns.
+async fn outdated_pods(replicaset: &ReplicaSet, client: &Client) -> Result<Vec<Pod>, Error> {
+    let desired = replicaset
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.template.as_ref())
+        .and_then(|template| template.metadata.as_ref())
+        .and_then(|metadata| metadata.annotations.as_ref())
+        .and_then(|annotations| annotations.get(CONFIG_HASH_ANNOTATION));
+    let selector = replicaset
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.selector.match_labels.as_ref())
+        .map(|labels| {
+            labels
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .unwrap_or_default();
+
+    let pods: Api<Pod> =
+        Api::namespaced(client.clone(), &replicaset.namespace().unwrap_or_default());
+    let selected = pods
+        .list(&ListParams::default().labels(&selector))
+        .instrument(info_span!(
+            "kube.list",
+            otel.kind = "client",
+            k8s.kind = "Pod",
+            k8s.label_selector = %selector
+        ))
+        .await
+        .map_err(|source| Error::ReadFailed {
+            kind: "Pod".to_string(),
+            name: selector.clone(),
+            source,
+        })?;
+    Ok(selected
+        .items
+        .into_iter()
+        .filter(|pod| {
+            let owned_by_replicaset = replicaset
+                .metadata
+                .uid
+                .as_ref()
+                .is_none_or(|uid| pod.owner_references().iter().any(|owner| &owner.uid == uid));
+            owned_by_replicaset
+                && pod.metadata.deletion_timestamp.is_none()
+                && pod.annotations().get(CONFIG_HASH_ANNOTATION) != desired
+        })
+        .collect())
+}
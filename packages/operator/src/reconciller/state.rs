@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use k8s_openapi::jiff::Timestamp;
+use kube::{Resource, ResourceExt};
+use kube_runtime::reflector::{ObjectRef, Store};
+use serde::Serialize;
+
+use crate::{
+    crds::crd::{FieldError, Moodle, MoodleSpec, ValidationErrors},
+    reconciller::{
+        backoff::{BackoffState, ErrorBackoff},
+        hash::json_hash,
+    },
+};
+
+/// Live state of the Moodle controller, read by `/readyz` and `/api/v1/moodles`
+#[derive(Clone, Default)]
+pub struct ControllerState(Arc<RwLock<State>>);
+
+#[derive(Default)]
+struct State {
+    stores: Vec<Store<Moodle>>,
+    leader: bool,
+    synced: bool,
+    synced_at: Option<Instant>,
+    last_reconcile: Option<Instant>,
+    sites: HashMap<ObjectRef<Moodle>, SiteActivity>,
+}
+
+/// What the controller last did for one Moodle object
+#[derive(Debug, Clone, Default)]
+struct SiteActivity {
+    last_reconcile: Option<Timestamp>,
+    last_error: Option<String>,
+    validation_errors: Vec<FieldError>,
+    applied_spec_hash: Option<String>,
+    children: Vec<ChildSummary>,
+}
+
+/// Child resource applied for a Moodle object
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChildSummary {
+    pub kind: String,
+    pub name: String,
+    pub replicas: Option<i32>,
+    pub ready_replicas: Option<i32>,
+}
+
+/// Point-in-time view of the controller, as reported on `/readyz`
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub leader: bool,
+    pub synced: bool,
+    pub objects: usize,
+    pub active: usize,
+    pub last_reconcile_secs_ago: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// One managed Moodle site, as reported on `/api/v1/moodles`
+#[derive(Debug, Serialize)]
+pub struct SiteView {
+    pub namespace: String,
+    pub name: String,
+    pub generation: Option<i64>,
+    pub phase: Option<String>,
+    pub paused: bool,
+    pub replicas: i32,
+    pub ready_replicas: Option<i32>,
+    pub last_reconcile: Option<String>,
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub validation_errors: Vec<FieldError>,
+    pub children: Vec<ChildSummary>,
+    pub backoff: Option<BackoffState>,
+    pub spec_hash: SpecHash,
+}
+
+/// Hash of the spec in the cache versus the one last applied to the children
+#[derive(Debug, Serialize)]
+pub struct SpecHash {
+    pub desired: String,
+    pub observed: Option<String>,
+    pub in_sync: bool,
+}
+
+impl ControllerState {
+    /// Controllers were (re)started with these caches, which have not synced yet
+    pub fn started(&self, stores: Vec<Store<Moodle>>) {
+        let mut state = self.0.write().unwrap();
+        state.stores = stores;
+        state.synced = false;
+    }
+
+    /// This replica runs the controllers, always the case without leader election
+    pub fn set_leader(&self, leader: bool) {
+        self.0.write().unwrap().leader = leader;
+    }
+
+    /// The initial list of every watched namespace has been loaded into the caches
+    pub fn synced(&self) {
+        let mut state = self.0.write().unwrap();
+        state.synced = true;
+        state.synced_at = Some(Instant::now());
+    }
+
+    /// Controllers stopped, their caches are no longer updated
+    pub fn stopped(&self) {
+        self.0.write().unwrap().synced = false;
+    }
+
+    /// A reconcile pass of `moodle` finished, whatever its result
+    pub fn record_reconcile(&self, moodle: &ObjectRef<Moodle>) {
+        let mut state = self.0.write().unwrap();
+        state.last_reconcile = Some(Instant::now());
+        state
+            .sites
+            .entry(moodle.clone())
+            .or_default()
+            .last_reconcile = Some(Timestamp::now());
+
+        // Drop what was recorded for Moodle objects that left the caches
+        let State { stores, sites, .. } = &mut *state;
+        if sites.len() > stores.iter().map(Store::len).sum() {
+            sites.retain(|key, _| stores.iter().any(|store| store.get(key).is_some()));
+        }
+    }
+
+    /// Reconciling `moodle` failed or its spec was rejected
+    pub fn record_error(&self, moodle: &ObjectRef<Moodle>, error: String) {
+        let mut state = self.0.write().unwrap();
+        state.sites.entry(moodle.clone()).or_default().last_error = Some(error);
+    }
+
+    /// The spec of `moodle` was rejected with `errors`, nothing was applied
+    pub fn record_invalid(&self, moodle: &ObjectRef<Moodle>, errors: &ValidationErrors) {
+        let mut state = self.0.write().unwrap();
+        let site = state.sites.entry(moodle.clone()).or_default();
+        site.last_error = Some(errors.to_string());
+        site.validation_errors = errors.0.clone();
+    }
+
+    /// The spec of `moodle` was applied to `children`, clearing any previous error
+    pub fn record_applied(&self, moodle: &Moodle, children: Vec<ChildSummary>) {
+        let mut state = self.0.write().unwrap();
+        let site = state.sites.entry(ObjectRef::from_obj(moodle)).or_default();
+        site.last_error = None;
+        site.validation_errors.clear();
+        site.applied_spec_hash = Some(spec_hash(&moodle.spec));
+        site.children = children;
+    }
+
+    /// Ready once the caches are synced and active Moodle objects were reconciled within
+    /// `stale_after`.
+    /// - Paused and deleted objects wait for a change, so they are not expected to reconcile.
+    /// - Standby replicas are ready, they serve the webhooks while another one leads.
+    pub fn readiness(&self, stale_after: Duration) -> Readiness {
+        let state = self.0.read().unwrap();
+        let objects = state.stores.iter().map(Store::len).sum();
+        let active = state
+            .stores
+            .iter()
+            .flat_map(Store::state)
+            .filter(|moodle| !moodle.is_paused() && moodle.meta().deletion_timestamp.is_none())
+            .count();
+        let since_reconcile = state.last_reconcile.map(|at| at.elapsed());
+        // The first reconciles only start once the caches synced
+        let since_activity = state
+            .last_reconcile
+            .max(state.synced_at)
+            .map(|at| at.elapsed());
+
+        let message = if !state.leader {
+            None
+        } else if !state.synced {
+            Some("watch caches not synced".to_string())
+        } else if active > 0 && since_activity.is_none_or(|elapsed| elapsed > stale_after) {
+            Some(format!(
+                "no reconcile of {active} active sites within {stale_after:?}"
+            ))
+        } else {
+            None
+        };
+
+        Readiness {
+            ready: message.is_none(),
+            leader: state.leader,
+            synced: state.synced,
+            objects,
+            active,
+            last_reconcile_secs_ago: since_reconcile.map(|elapsed| elapsed.as_secs()),
+            message,
+        }
+    }
+
+    /// Every cached Moodle object, sorted by namespace and name
+    pub fn sites(&self, backoff: &ErrorBackoff) -> Vec<SiteView> {
+        let state = self.0.read().unwrap();
+        let mut sites: Vec<SiteView> = state
+            .stores
+            .iter()
+            .flat_map(Store::state)
+            .map(|moodle| state.view(&moodle, backoff))
+            .collect();
+        sites.sort_by(|a, b| (&a.namespace, &a.name).cmp(&(&b.namespace, &b.name)));
+        sites
+    }
+
+    /// The cached Moodle object `namespace/name`, if it is watched
+    pub fn site(&self, namespace: &str, name: &str, backoff: &ErrorBackoff) -> Option<SiteView> {
+        let state = self.0.read().unwrap();
+        let key = ObjectRef::new(name).within(namespace);
+        state
+            .stores
+            .iter()
+            .find_map(|store| store.get(&key))
+            .map(|moodle| state.view(&moodle, backoff))
+    }
+}
+
+impl State {
+    fn view(&self, moodle: &Moodle, backoff: &ErrorBackoff) -> SiteView {
+        let key = ObjectRef::from_obj(moodle);
+        let site = self.sites.get(&key).cloned().unwrap_or_default();
+        let desired = spec_hash(&moodle.spec);
+        let status = moodle.status.as_ref();
+
+        SiteView {
+            namespace: moodle.namespace().unwrap_or_default(),
+            name: moodle.name_any(),
+            generation: moodle.meta().generation,
+            phase: status.and_then(|status| status.phase.clone()),
+            paused: moodle.is_paused(),
+            replicas: moodle.spec.replicas,
+            ready_replicas: status.and_then(|status| status.ready_replicas),
+            last_reconcile: site.last_reconcile.map(|at| at.to_string()),
+            last_error: site.last_error,
+            validation_errors: site.validation_errors,
+            children: site.children,
+            backoff: backoff.state(&key),
+            spec_hash: SpecHash {
+                in_sync: site.applied_spec_hash.as_ref() == Some(&desired),
+                desired,
+                observed: site.applied_spec_hash,
+            },
+        }
+    }
+}
+
+/// Hash of `spec` without the inline database password, as the hash is served unauthenticated
+fn spec_hash(spec: &MoodleSpec) -> String {
+    let mut spec = spec.clone();
+    spec.database.password.clear();
+    json_hash(&spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readiness_requires_sync() {
+        let state = ControllerState::default();
+        let stale_after = Duration::from_secs(60);
+        assert!(state.readiness(stale_after).ready, "standby replica");
+
+        state.set_leader(true);
+        assert!(!state.readiness(stale_after).ready);
+
+        state.started(Vec::new());
+        assert!(!state.readiness(stale_after).ready);
+
+        state.synced();
+        let readiness = state.readiness(stale_after);
+        assert!(readiness.ready);
+        assert_eq!(readiness.objects, 0);
+
+        state.stopped();
+        assert!(!state.readiness(stale_after).ready);
+    }
+
+    #[test]
+    fn test_readiness_ignores_paused_sites() {
+        let (store, mut writer) = kube_runtime::reflector::store::<Moodle>();
+        let mut moodle: Moodle = serde_json::from_value(serde_json::json!({
+            "apiVersion": "moodle.adorsys.com/v1",
+            "kind": "Moodle",
+            "metadata": {
+                "name": "site",
+                "namespace": "lms",
+                "annotations": { crate::crds::crd::PAUSED_ANNOTATION: "true" }
+            },
+            "spec": {
+                "pvcName": "moodle-data",
+                "database": {
+                    "host": "db", "user": "moodle", "password": "secret", "type": "pgsql", "name": "moodle"
+                }
+            }
+        }))
+        .unwrap();
+        writer.apply_watcher_event(&kube_runtime::watcher::Event::Apply(moodle.clone()));
+
+        let state = ControllerState::default();
+        state.set_leader(true);
+        state.started(vec![store]);
+        state.synced();
+        std::thread::sleep(Duration::from_millis(5));
+
+        let stale_after = Duration::from_millis(1);
+        let readiness = state.readiness(stale_after);
+        assert!(readiness.ready);
+        assert_eq!((readiness.objects, readiness.active), (1, 0));
+
+        moodle.metadata.annotations = None;
+        writer.apply_watcher_event(&kube_runtime::watcher::Event::Apply(moodle));
+        assert!(!state.readiness(stale_after).ready);
+    }
+
+    #[test]
+    fn test_spec_hash_ignores_inline_password() {
+        let spec: MoodleSpec = serde_json::from_value(serde_json::json!({
+            "pvcName": "moodle-data",
+            "database": {
+                "host": "db", "user": "moodle", "password": "secret", "type": "pgsql", "name": "moodle"
+            }
+        }))
+        .unwrap();
+        let mut changed = spec.clone();
+        changed.database.password = "other".to_string();
+        assert_eq!(spec_hash(&spec), spec_hash(&changed));
+
+        changed.replicas = 2;
+        assert_ne!(spec_hash(&spec), spec_hash(&changed));
+    }
+}
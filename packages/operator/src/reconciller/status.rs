@@ -0,0 +1,117 @@
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use k8s_openapi::jiff::Timestamp;
+use kube::api::{Patch, PatchParams};
+use kube::{Api, Resource, ResourceExt};
+use serde_json::json;
+use tracing::{info_span, Instrument};
+
+use crate::crds::crd::{Moodle, MoodleStatus};
+use crate::error::Error;
+use crate::reconciller::apply::FIELD_MANAGER;
+
+/// Condition type reporting field ownership conflicts on child resources
+pub const CONDITION_CONFLICT: &str = "Conflict";
+/// Condition type reporting whether the spec passed validation, listing rejected fields
+pub const CONDITION_SPEC_VALID: &str = "SpecValid";
+/// Condition type reporting that reconciliation is paused by annotation
+pub const CONDITION_PAUSED: &str = "Paused";
+
+/// Every replica of the site is ready
+pub const PHASE_RUNNING: &str = "Running";
+/// Child resources are applied but not all replicas are ready yet
+pub const PHASE_PROGRESSING: &str = "Progressing";
+/// The spec failed validation and nothing was applied
+pub const PHASE_INVALID: &str = "Invalid";
+/// Applying child resources failed
+pub const PHASE_FAILED: &str = "Failed";
+
+/// Build a condition for `moodle`, stamped with its current generation
+pub fn condition(
+    moodle: &Moodle,
+    type_: &str,
+    status: bool,
+    reason: &str,
+    message: &str,
+) -> Condition {
+    Condition {
+        type_: type_.to_string(),
+        status: if status { "True" } else { "False" }.to_string(),
+        reason: reason.to_string(),
+        message: message.to_string(),
+        observed_generation: moodle.meta().generation,
+        last_transition_time: Time(Timestamp::now()),
+    }
+}
+
+/// Insert or replace a condition by type.
+/// - The previous transition time is kept when the status did not change.
+pub fn set_condition(conditions: &mut Vec<Condition>, mut condition: Condition) {
+    match conditions.iter_mut().find(|c| c.type_ == condition.type_) {
+        Some(existing) => {
+            if existing.status == condition.status {
+                condition.last_transition_time = existing.last_transition_time.clone();
+            }
+            *existing = condition;
+        }
+        None => conditions.push(condition),
+    }
+}
+
+/// Replace the Moodle status subresource, skipping the call when nothing changed
+pub async fn patch_status(
+    moodle: &Moodle,
+    api: &Api<Moodle>,
+    status: &MoodleStatus,
+) -> Result<(), Error> {
+    if moodle.status.as_ref() == Some(status) {
+        return Ok(());
+    }
+
+    // A merge patch, still recorded under the operator's field manager
+    let params = PatchParams {
+        field_manager: Some(FIELD_MANAGER.to_string()),
+        ..Default::default()
+    };
+    let patch = Patch::Merge(json!({ "status": status }));
+    api.patch_status(&moodle.name_any(), &params, &patch)
+        .instrument(info_span!(
+            "kube.patch_status",
+            otel.kind = "client",
+            k8s.kind = "Moodle",
+            k8s.name = %moodle.name_any()
+        ))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cond(type_: &str, status: &str, seconds: i64) -> Condition {
+        Condition {
+            type_: type_.to_string(),
+            status: status.to_string(),
+            reason: "Test".to_string(),
+            message: String::new(),
+            observed_generation: None,
+            last_transition_time: Time(Timestamp::from_second(seconds).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_set_condition_keeps_transition_time_when_unchanged() {
+        let mut conditions = vec![cond(CONDITION_CONFLICT, "False", 10)];
+        set_condition(&mut conditions, cond(CONDITION_CONFLICT, "False", 20));
+        assert_eq!(conditions.len(), 1);
+        assert_eq!(conditions[0].last_transition_time.0.as_second(), 10);
+    }
+
+    #[test]
+    fn test_set_condition_updates_transition_time_on_change() {
+        let mut conditions = vec![cond(CONDITION_CONFLICT, "False", 10)];
+        set_condition(&mut conditions, cond(CONDITION_CONFLICT, "True", 20));
+        assert_eq!(conditions[0].status, "True");
+        assert_eq!(conditions[0].last_transition_time.0.as_second(), 20);
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::{Arc, Mutex};
+
+use futures::{channel::mpsc, Stream};
+use kube_runtime::reflector::ObjectRef;
+
+use crate::crds::crd::Moodle;
+
+/// Schedules immediate reconciles on the running controllers, outside of watch events
+#[derive(Clone, Default)]
+pub struct ReconcileTrigger {
+    controllers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+/// Controller watching `namespace`, or every namespace when `None`
+struct Subscriber {
+    namespace: Option<String>,
+    sender: mpsc::UnboundedSender<ObjectRef<Moodle>>,
+}
+
+impl ReconcileTrigger {
+    /// Stream of reconcile requests for the controller watching `namespace`.
+    /// - Dropping the stream, e.g. when the controller restarts, unsubscribes it.
+    pub fn subscribe(&self, namespace: Option<String>) -> impl Stream<Item = ObjectRef<Moodle>> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.controllers
+            .lock()
+            .unwrap()
+            .push(Subscriber { namespace, sender });
+        receiver
+    }
+
+    /// Ask the controller watching the namespace of `moodle` to reconcile it now.
+    /// - Returns `false` when no running controller watches that namespace.
+    pub fn trigger(&self, moodle: &ObjectRef<Moodle>) -> bool {
+        let mut controllers = self.controllers.lock().unwrap();
+        controllers.retain(|subscriber| !subscriber.sender.is_closed());
+
+        controllers
+            .iter()
+            .filter(|subscriber| {
+                subscriber.namespace.is_none() || subscriber.namespace == moodle.namespace
+            })
+            .any(|subscriber| subscriber.sender.unbounded_send(moodle.clone()).is_ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_trigger_reaches_controller_of_namespace() {
+        let trigger = ReconcileTrigger::default();
+        let mut blue = Box::pin(trigger.subscribe(Some("blue".to_string())));
+        let green = trigger.subscribe(Some("green".to_string()));
+        drop(green);
+
+        let moodle = ObjectRef::new("site").within("blue");
+        assert!(trigger.trigger(&moodle));
+        assert_eq!(blue.next().await, Some(moodle));
+
+        assert!(!trigger.trigger(&ObjectRef::new("site").within("green")));
+    }
+}
@@ -1,16 +1,58 @@
 use bytes::Bytes;
-use http_body_util::{BodyExt, Full};
+use futures::future::Either;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
 use hyper::{
     body::Incoming as IncomingBody, header, server::conn::http1, service::service_fn, Method,
     Request, Response, StatusCode,
 };
+use hyper_util::{
+    rt::TokioIo,
+    server::graceful::{GracefulShutdown, Watcher},
+};
 use kube::Client;
-use std::net::SocketAddr;
-use tokio::net::TcpListener;
-use tracing::info;
+use kube_runtime::reflector::ObjectRef;
+use prometheus::{Encoder, Registry, TextEncoder};
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use serde_json::json;
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::{supervisor::TaskHealth, telemetry::logging::LogLevel, webhook, Data};
+
+/// Shared state available to every request handler
+#[derive(Clone)]
+pub struct ServerState {
+    pub health: TaskHealth,
+    pub registry: Option<Registry>,
+    pub log_level: LogLevel,
+    pub data: Arc<Data>,
+}
 
 static NOTFOUND: &[u8] = b"Not Found";
 
+/// Largest request body read by a handler, larger ones are answered with 413
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 type BoxBody = http_body_util::combinators::BoxBody<Bytes, hyper::Error>;
 
@@ -20,57 +62,416 @@ fn full<T: Into<Bytes>>(chunk: T) -> BoxBody {
         .boxed()
 }
 
-async fn handle_request(req: Request<IncomingBody>) -> Result<Response<BoxBody>> {
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<BoxBody> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(body.to_string()))
+        .unwrap()
+}
+
+/// Operations endpoints, served over plain HTTP
+async fn handle_request(
+    req: Request<IncomingBody>,
+    state: ServerState,
+) -> Result<Response<BoxBody>> {
     match (req.method(), req.uri().path()) {
-        (&Method::GET, "/readyz") => match check_kube_readyz().await {
-            Ok(_) => {
-                let body = r#"{"status": "ok"}"#;
-                Ok(Response::builder()
-                    .status(StatusCode::OK)
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(full(body))
-                    .unwrap())
+        // Process is up and serving requests; nothing external is checked
+        (&Method::GET, "/livez") => Ok(json_response(StatusCode::OK, json!({ "status": "ok" }))),
+
+        (&Method::GET, "/readyz") => {
+            let tasks = state.health.snapshot();
+            if !state.health.all_running() {
+                return Ok(json_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    json!({ "status": "error", "message": "background tasks not running", "tasks": tasks }),
+                ));
+            }
+
+            let controller = state
+                .data
+                .state
+                .readiness(state.data.config.readiness_stale_after);
+            if !controller.ready {
+                return Ok(json_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    json!({ "status": "error", "message": controller.message, "tasks": tasks, "controller": controller }),
+                ));
+            }
+
+            match check_kube_readyz(&state.data.client).await {
+                Ok(_) => Ok(json_response(
+                    StatusCode::OK,
+                    json!({ "status": "ok", "tasks": tasks, "controller": controller }),
+                )),
+                Err(err) => Ok(json_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    json!({ "status": "error", "message": err.to_string(), "tasks": tasks, "controller": controller }),
+                )),
             }
-            Err(err) => {
-                let body = format!(r#"{{"status": "error", "message": "{err}"}}"#);
+        }
+
+        (&Method::GET, "/metrics") => match &state.registry {
+            Some(registry) => {
+                let encoder = TextEncoder::new();
+                let mut buffer = Vec::new();
+                encoder.encode(&registry.gather(), &mut buffer)?;
+
                 Ok(Response::builder()
-                    .status(StatusCode::SERVICE_UNAVAILABLE)
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(full(body))
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, encoder.format_type())
+                    .body(full(buffer))
                     .unwrap())
             }
+            None => Ok(not_found()),
         },
 
-        _ => Ok(Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(full(NOTFOUND))
-            .unwrap()),
+        (&Method::GET, "/api/v1/moodles") => Ok(json_response(
+            StatusCode::OK,
+            json!({ "items": state.data.state.sites(&state.data.backoff) }),
+        )),
+
+        (&Method::GET, path) if path.starts_with("/api/v1/moodles/") => {
+            match moodle_path(path).as_slice() {
+                [namespace, name] => {
+                    match state.data.state.site(namespace, name, &state.data.backoff) {
+                        Some(site) => Ok(json_response(StatusCode::OK, json!(site))),
+                        None => Ok(json_response(
+                            StatusCode::NOT_FOUND,
+                            json!({ "status": "error", "message": format!("Moodle {namespace}/{name} is not watched") }),
+                        )),
+                    }
+                }
+                _ => Ok(not_found()),
+            }
+        }
+
+        (&Method::POST, path) if path.starts_with("/api/v1/moodles/") => {
+            let [namespace, name, "reconcile"] = moodle_path(path)[..] else {
+                return Ok(not_found());
+            };
+            if let Some(response) = unauthorized(&req, state.data.config.api_token.as_deref()) {
+                return Ok(response);
+            }
+            if state
+                .data
+                .state
+                .site(namespace, name, &state.data.backoff)
+                .is_none()
+            {
+                return Ok(json_response(
+                    StatusCode::NOT_FOUND,
+                    json!({ "status": "error", "message": format!("Moodle {namespace}/{name} is not watched") }),
+                ));
+            }
+
+            // Retry right away and restart the error backoff from its base delay
+            let moodle = ObjectRef::new(name).within(namespace);
+            state.data.backoff.reset(&moodle);
+            if !state.data.trigger.trigger(&moodle) {
+                return Ok(json_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    json!({ "status": "error", "message": "controller not running" }),
+                ));
+            }
+
+            info!("Reconcile of Moodle {namespace}/{name} requested over the API");
+            Ok(json_response(
+                StatusCode::ACCEPTED,
+                json!({ "status": "accepted" }),
+            ))
+        }
+
+        (&Method::GET, "/debug/loglevel") => Ok(json_response(
+            StatusCode::OK,
+            json!({ "filter": state.log_level.current() }),
+        )),
+
+        // Body is a filter in `RUST_LOG` syntax, e.g. `debug` or `info,kube=debug`
+        (&Method::PUT, "/debug/loglevel") => {
+            if let Some(response) = unauthorized(&req, state.data.config.api_token.as_deref()) {
+                return Ok(response);
+            }
+            let body = match read_body(req).await? {
+                Ok(body) => body,
+                Err(response) => return Ok(response),
+            };
+            let directives = String::from_utf8_lossy(&body).trim().to_string();
+
+            match state.log_level.set(&directives) {
+                Ok(()) => {
+                    info!("Log filter changed to '{directives}'");
+                    Ok(json_response(
+                        StatusCode::OK,
+                        json!({ "filter": state.log_level.current() }),
+                    ))
+                }
+                Err(err) => Ok(json_response(
+                    StatusCode::BAD_REQUEST,
+                    json!({ "status": "error", "message": err.to_string() }),
+                )),
+            }
+        }
+
+        _ => Ok(not_found()),
+    }
+}
+
+/// Admission and conversion endpoints, served only over TLS for the API server
+async fn handle_webhook_request(
+    req: Request<IncomingBody>,
+    state: ServerState,
+) -> Result<Response<BoxBody>> {
+    match (req.method(), req.uri().path()) {
+        (&Method::POST, "/validate") => {
+            let body = match read_body(req).await? {
+                Ok(body) => body,
+                Err(response) => return Ok(response),
+            };
+            let review = webhook::validate(&body, &state.data.config.spec_defaults);
+            Ok(json_response(StatusCode::OK, serde_json::to_value(review)?))
+        }
+
+        (&Method::POST, "/mutate") => {
+            let body = match read_body(req).await? {
+                Ok(body) => body,
+                Err(response) => return Ok(response),
+            };
+            let review = webhook::mutate(&body, &state.data.config.spec_defaults);
+            Ok(json_response(StatusCode::OK, serde_json::to_value(review)?))
+        }
+
+        (&Method::POST, "/convert") => {
+            let body = match read_body(req).await? {
+                Ok(body) => body,
+                Err(response) => return Ok(response),
+            };
+            let review = webhook::conversion(&body);
+            Ok(json_response(StatusCode::OK, serde_json::to_value(review)?))
+        }
+
+        _ => Ok(not_found()),
+    }
+}
+
+/// Body of `req`, or a 413 response once it exceeds `MAX_BODY_BYTES`
+async fn read_body(
+    req: Request<IncomingBody>,
+) -> Result<std::result::Result<Bytes, Response<BoxBody>>> {
+    match Limited::new(req.into_body(), MAX_BODY_BYTES)
+        .collect()
+        .await
+    {
+        Ok(collected) => Ok(Ok(collected.to_bytes())),
+        Err(err) if err.is::<LengthLimitError>() => Ok(Err(json_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            json!({ "status": "error", "message": format!("request body exceeds {MAX_BODY_BYTES} bytes") }),
+        ))),
+        Err(err) => Err(err),
+    }
+}
+
+/// Rejection response unless the `Authorization: Bearer` header matches the configured API token
+fn unauthorized<B>(req: &Request<B>, token: Option<&str>) -> Option<Response<BoxBody>> {
+    let Some(token) = token else {
+        return Some(json_response(
+            StatusCode::FORBIDDEN,
+            json!({ "status": "error", "message": "API_TOKEN is not configured" }),
+        ));
+    };
+
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match provided {
+        Some(provided) if constant_time_eq(provided.as_bytes(), token.as_bytes()) => None,
+        _ => Some(json_response(
+            StatusCode::UNAUTHORIZED,
+            json!({ "status": "error", "message": "missing or invalid bearer token" }),
+        )),
     }
 }
 
-pub async fn start_server(bind_address: SocketAddr) -> Result<()> {
+/// Compare secrets without leaking the length of the matching prefix through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Segments of a path below `/api/v1/moodles/`, e.g. `[namespace, name]`
+fn moodle_path(path: &str) -> Vec<&str> {
+    path.trim_start_matches("/api/v1/moodles/")
+        .trim_end_matches('/')
+        .split('/')
+        .collect()
+}
+
+fn not_found() -> Response<BoxBody> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(full(NOTFOUND))
+        .unwrap()
+}
+
+/// Serve HTTP requests until `shutdown` is cancelled.
+/// - Without `tls` only the operations endpoints are served, with it only the webhook ones.
+/// - Stops accepting new connections, then waits for open ones to complete.
+pub async fn start_server(
+    bind_address: SocketAddr,
+    shutdown: CancellationToken,
+    state: ServerState,
+    tls: Option<TlsAcceptor>,
+) -> Result<()> {
     let listener = TcpListener::bind(bind_address).await?;
+    let graceful = GracefulShutdown::new();
 
-    info!("Metrics Server running at http://{bind_address}");
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    info!("Server running at {scheme}://{bind_address}");
 
     loop {
-        let (stream, _) = listener.accept().await?;
+        let stream = tokio::select! {
+            accepted = listener.accept() => accepted?.0,
+            _ = shutdown.cancelled() => break,
+        };
+
+        let watcher = graceful.watcher();
+        let state = state.clone();
+        let tls = tls.clone();
+        let webhooks = tls.is_some();
 
         // Spawn a new task to handle the incoming HTTP connection
         tokio::spawn(async move {
-            let io = hyper_util::rt::TokioIo::new(stream);
-            let service = service_fn(handle_request);
-
-            if let Err(err) = http1::Builder::new().serve_connection(io, service).await {
-                eprintln!("Error serving connection: {err:?}");
+            let served = match tls {
+                Some(tls) => match tls.accept(stream).await {
+                    Ok(stream) => serve_connection(watcher, stream, state, webhooks).await,
+                    Err(err) => {
+                        // Usually a client or probe not speaking TLS, not worth a warning
+                        debug!("TLS handshake failed: {err}");
+                        return;
+                    }
+                },
+                None => serve_connection(watcher, stream, state, webhooks).await,
+            };
+            if let Err(err) = served {
+                warn!("Error serving {scheme} connection: {err:?}");
             }
         });
     }
+
+    drop(listener);
+    info!("Draining open {scheme} connections");
+    graceful.shutdown().await;
+
+    Ok(())
+}
+
+async fn serve_connection<S>(
+    watcher: Watcher,
+    stream: S,
+    state: ServerState,
+    webhooks: bool,
+) -> std::result::Result<(), hyper::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let io = TokioIo::new(stream);
+    let service = service_fn(move |req| match webhooks {
+        true => Either::Left(handle_webhook_request(req, state.clone())),
+        false => Either::Right(handle_request(req, state.clone())),
+    });
+    watcher
+        .watch(http1::Builder::new().serve_connection(io, service))
+        .await
+}
+
+/// TLS acceptor serving the PEM certificate chain and private key from `cert_dir`.
+/// - Expects `tls.crt` and `tls.key`, as mounted from a `kubernetes.io/tls` Secret.
+/// - Fails unless the current pair loads; renewed pairs are picked up by `ReloadingCert`.
+pub fn tls_acceptor(cert_dir: &Path) -> Result<TlsAcceptor> {
+    let resolver = ReloadingCert::new(cert_dir)?;
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(resolver));
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Certificate of `cert_dir`, reloaded on the first handshake after `tls.crt` or `tls.key` change.
+/// - A renewed pair that fails to load is logged and the previous certificate kept serving.
+#[derive(Debug)]
+struct ReloadingCert {
+    cert_dir: PathBuf,
+    loaded: RwLock<(Modified, Arc<CertifiedKey>)>,
+}
+
+/// Modification times of `tls.crt` and `tls.key`
+type Modified = [Option<SystemTime>; 2];
+
+impl ReloadingCert {
+    fn new(cert_dir: &Path) -> Result<Self> {
+        let modified = modified(cert_dir);
+        let key = load_certified_key(cert_dir)?;
+        Ok(Self {
+            cert_dir: cert_dir.to_path_buf(),
+            loaded: RwLock::new((modified, Arc::new(key))),
+        })
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let modified = modified(&self.cert_dir);
+        {
+            let (loaded_at, key) = &*self.loaded.read().unwrap();
+            if *loaded_at == modified {
+                return Some(key.clone());
+            }
+        }
+
+        let mut loaded = self.loaded.write().unwrap();
+        // Not retried until the files change again, e.g. once a partial write completes
+        loaded.0 = modified;
+        match load_certified_key(&self.cert_dir) {
+            Ok(key) => {
+                info!(
+                    "Reloaded webhook certificate from {}",
+                    self.cert_dir.display()
+                );
+                loaded.1 = Arc::new(key);
+            }
+            Err(err) => warn!(
+                "Failed to reload webhook certificate from {}, keeping the previous one: {err}",
+                self.cert_dir.display()
+            ),
+        }
+        Some(loaded.1.clone())
+    }
 }
 
-async fn check_kube_readyz() -> Result<String> {
-    let client = Client::try_default().await?;
+fn modified(cert_dir: &Path) -> Modified {
+    ["tls.crt", "tls.key"].map(|file| {
+        fs::metadata(cert_dir.join(file))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    })
+}
+
+fn load_certified_key(cert_dir: &Path) -> Result<CertifiedKey> {
+    let certs = CertificateDer::pem_file_iter(cert_dir.join("tls.crt"))?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(cert_dir.join("tls.key"))?;
+    Ok(CertifiedKey::from_der(
+        certs,
+        key,
+        &ring::default_provider(),
+    )?)
+}
 
+async fn check_kube_readyz(client: &Client) -> Result<String> {
     let req = Request::builder().uri("/readyz?verbose").body(Vec::new())?; // Empty body
 
     let text = client.request_text(req).await?;
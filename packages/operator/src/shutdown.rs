@@ -0,0 +1,54 @@
+use std::fmt;
+use std::process::ExitCode;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::warn;
+
+/// Why the operator is shutting down
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// SIGTERM, usually sent by the kubelet when the pod is stopped
+    Terminated,
+    /// SIGINT, usually Ctrl-C during local runs
+    Interrupted,
+    /// A background task failed and the operator cannot continue
+    TaskFailed(String),
+}
+
+impl ShutdownReason {
+    /// Process exit code matching the shutdown reason
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            ShutdownReason::Terminated => ExitCode::SUCCESS,
+            ShutdownReason::Interrupted => ExitCode::from(130),
+            ShutdownReason::TaskFailed(_) => ExitCode::FAILURE,
+        }
+    }
+}
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShutdownReason::Terminated => write!(f, "received SIGTERM"),
+            ShutdownReason::Interrupted => write!(f, "received SIGINT"),
+            ShutdownReason::TaskFailed(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Wait until the process receives SIGTERM or SIGINT
+pub async fn wait_for_signal() -> ShutdownReason {
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(e) => {
+            warn!("Failed to install SIGTERM handler: {e}");
+            let _ = tokio::signal::ctrl_c().await;
+            return ShutdownReason::Interrupted;
+        }
+    };
+
+    tokio::select! {
+        _ = sigterm.recv() => ShutdownReason::Terminated,
+        _ = tokio::signal::ctrl_c() => ShutdownReason::Interrupted,
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// How a supervised task is restarted after it fails
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    /// Consecutive failures tolerated before escalating
+    pub max_failures: u32,
+    /// Delay before the first restart, doubled on every further failure
+    pub initial_backoff: Duration,
+    /// Upper bound for the restart delay
+    pub max_backoff: Duration,
+    /// A run lasting at least this long resets the failure count
+    pub reset_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            reset_after: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Delay before restarting after `failures` consecutive failures
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Current state of a supervised task
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "camelCase")]
+pub enum TaskState {
+    Running,
+    Restarting { failures: u32, last_error: String },
+    Failed { last_error: String },
+    Stopped,
+}
+
+/// Shared view of every supervised task, read by `/readyz`
+#[derive(Debug, Clone, Default)]
+pub struct TaskHealth(Arc<RwLock<BTreeMap<&'static str, TaskState>>>);
+
+impl TaskHealth {
+    fn set(&self, name: &'static str, state: TaskState) {
+        self.0.write().unwrap().insert(name, state);
+    }
+
+    /// Snapshot of all task states keyed by task name
+    pub fn snapshot(&self) -> BTreeMap<&'static str, TaskState> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// True when every registered task is running
+    pub fn all_running(&self) -> bool {
+        self.0
+            .read()
+            .unwrap()
+            .values()
+            .all(|state| *state == TaskState::Running)
+    }
+}
+
+/// Spawns background tasks and restarts them with backoff when they fail.
+/// - A task exceeding `RestartPolicy::max_failures` is reported on the escalation channel.
+#[derive(Clone)]
+pub struct Supervisor {
+    policy: RestartPolicy,
+    health: TaskHealth,
+    shutdown: CancellationToken,
+    escalate: mpsc::Sender<String>,
+}
+
+impl Supervisor {
+    pub fn new(
+        policy: RestartPolicy,
+        shutdown: CancellationToken,
+        escalate: mpsc::Sender<String>,
+    ) -> Self {
+        Self {
+            policy,
+            health: TaskHealth::default(),
+            shutdown,
+            escalate,
+        }
+    }
+
+    pub fn health(&self) -> TaskHealth {
+        self.health.clone()
+    }
+
+    /// Run `task` under supervision until it stops cleanly after shutdown
+    pub fn spawn<F, Fut, E>(&self, name: &'static str, mut task: F) -> JoinHandle<()>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send,
+        E: Display,
+    {
+        let supervisor = self.clone();
+
+        tokio::spawn(async move {
+            let policy = &supervisor.policy;
+            let mut failures = 0;
+
+            loop {
+                supervisor.health.set(name, TaskState::Running);
+                let started = Instant::now();
+
+                let last_error = match task().await {
+                    Ok(()) if supervisor.shutdown.is_cancelled() => {
+                        supervisor.health.set(name, TaskState::Stopped);
+                        return;
+                    }
+                    Ok(()) => "exited unexpectedly".to_string(),
+                    Err(e) => e.to_string(),
+                };
+
+                if started.elapsed() >= policy.reset_after {
+                    failures = 0;
+                }
+                failures += 1;
+
+                if failures >= policy.max_failures {
+                    error!("Task {name} failed {failures} times, giving up: {last_error}");
+                    supervisor.health.set(
+                        name,
+                        TaskState::Failed {
+                            last_error: last_error.clone(),
+                        },
+                    );
+                    let _ = supervisor
+                        .escalate
+                        .send(format!("{name} failed: {last_error}"))
+                        .await;
+                    return;
+                }
+
+                let backoff = policy.backoff(failures);
+                warn!(
+                    "Task {name} failed ({failures}/{}), restarting in {backoff:?}: {last_error}",
+                    policy.max_failures
+                );
+                supervisor.health.set(
+                    name,
+                    TaskState::Restarting {
+                        failures,
+                        last_error,
+                    },
+                );
+
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => info!("Restarting task {name}"),
+                    _ = supervisor.shutdown.cancelled() => {
+                        supervisor.health.set(name, TaskState::Stopped);
+                        return;
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_and_caps() {
+        let policy = RestartPolicy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10),
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(2), Duration::from_secs(2));
+        assert_eq!(policy.backoff(4), Duration::from_secs(8));
+        assert_eq!(policy.backoff(5), Duration::from_secs(10));
+        assert_eq!(policy.backoff(100), Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_escalates_after_max_failures() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let policy = RestartPolicy {
+            max_failures: 3,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let supervisor = Supervisor::new(policy, CancellationToken::new(), tx);
+
+        let handle = supervisor.spawn("flaky", || async { Err::<(), _>("boom") });
+        handle.await.unwrap();
+
+        assert_eq!(rx.recv().await.unwrap(), "flaky failed: boom");
+        assert!(!supervisor.health().all_running());
+    }
+}
@@ -1,61 +1,140 @@
 use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
-use opentelemetry_otlp::{LogExporter, Protocol, WithExportConfig};
-use opentelemetry_sdk::logs::SdkLoggerProvider;
-use tracing_subscriber::{prelude::*, EnvFilter};
+use opentelemetry_sdk::{logs::SdkLoggerProvider, trace::SdkTracer};
+use std::{str::FromStr, time::Duration};
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    prelude::*,
+    reload, EnvFilter, Registry,
+};
 
-use crate::telemetry::resource::get_resource;
+use crate::telemetry::{
+    otlp::{or_stdout, OtlpConfig},
+    resource::get_resource,
+};
+
+/// Output format of logs written to stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown log format '{0}', expected 'text' or 'json'")]
+pub struct UnknownLogFormat(String);
+
+impl FromStr for LogFormat {
+    type Err = UnknownLogFormat;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "text" | "" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(UnknownLogFormat(value.to_string())),
+        }
+    }
+}
+
+/// Handle to change the log filter of the running operator
+#[derive(Clone)]
+pub struct LogLevel(reload::Handle<EnvFilter, Registry>);
+
+impl LogLevel {
+    /// Directives of the active filter, in `RUST_LOG` syntax
+    pub fn current(&self) -> Option<String> {
+        self.0.with_current(|filter| filter.to_string()).ok()
+    }
+
+    /// Replace the active filter with `directives`, e.g. `debug` or `info,operator=trace`
+    pub fn set(&self, directives: &str) -> anyhow::Result<()> {
+        let filter = EnvFilter::try_new(directives)?;
+        self.0.reload(filter)?;
+        Ok(())
+    }
+}
 
 /// Struct to hold the logger provider to keep it alive
 pub struct LoggerHandle {
     pub provider: SdkLoggerProvider,
+    log_level: LogLevel,
 }
 
 impl LoggerHandle {
     /// Initialize logging and tracing with OpenTelemetry and tracing subscriber.
-    /// - Sets up a log exporter to send logs to the specified OTLP endpoint.
-    pub fn init(endpoint: &str) -> Self {
-        let exporter = LogExporter::builder()
-            .with_http()
-            .with_endpoint(endpoint)
-            .with_protocol(Protocol::HttpBinary)
-            .build()
-            .expect("Failed to create log exporter");
-
-        let provider = SdkLoggerProvider::builder()
-            .with_batch_exporter(exporter)
-            .with_resource(get_resource())
-            .build();
-
-        let otel_layer = OpenTelemetryTracingBridge::new(&provider);
-
-        let env_filter = EnvFilter::try_from_default_env()
-            .unwrap_or_else(|_| EnvFilter::new("info"))
-            .add_directive("hyper=off".parse().unwrap())
-            .add_directive("tonic=off".parse().unwrap())
-            .add_directive("h2=off".parse().unwrap())
-            .add_directive("reqwest=off".parse().unwrap());
-
-        let otel_layer = otel_layer.with_filter(env_filter);
-
-        let fmt_layer = tracing_subscriber::fmt::layer()
-            .with_thread_names(false)
-            .with_target(false)
-            .with_filter(
-                EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
-            );
+    /// - Sets up a log exporter to send logs to the OTLP endpoint in `otlp`, if any.
+    /// - Exports `tracing` spans through `tracer`; logs emitted inside a span carry its trace ID.
+    /// - Filters every layer with `RUST_LOG`, which can be changed later through `log_level`.
+    pub fn init(otlp: Option<&OtlpConfig>, tracer: SdkTracer, format: LogFormat) -> Self {
+        let mut builder = SdkLoggerProvider::builder().with_resource(get_resource());
+        if let Some(exporter) = otlp.and_then(|otlp| or_stdout(otlp.signal, otlp.log_exporter())) {
+            builder = builder.with_batch_exporter(exporter);
+        }
+        let provider = builder.build();
+
+        let env_filter =
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+        let (filter_layer, reload_handle) = reload::Layer::new(env_filter);
+
+        // Keep the exporters' own HTTP/gRPC clients from feeding logs back into OTLP
+        let otel_layer = OpenTelemetryTracingBridge::new(&provider).with_filter(
+            Targets::new()
+                .with_default(LevelFilter::TRACE)
+                .with_target("hyper", LevelFilter::OFF)
+                .with_target("tonic", LevelFilter::OFF)
+                .with_target("h2", LevelFilter::OFF)
+                .with_target("reqwest", LevelFilter::OFF),
+        );
+
+        let trace_layer = tracing_opentelemetry::layer().with_tracer(tracer);
+
+        let text_layer = (format == LogFormat::Text).then(|| {
+            tracing_subscriber::fmt::layer()
+                .with_thread_names(false)
+                .with_target(false)
+        });
+
+        let json_layer = (format == LogFormat::Json).then(|| {
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(false)
+        });
 
         tracing_subscriber::registry()
+            .with(filter_layer)
+            .with(trace_layer)
             .with(otel_layer)
-            .with(fmt_layer)
+            .with(text_layer)
+            .with(json_layer)
             .init();
 
-        Self { provider }
+        Self {
+            provider,
+            log_level: LogLevel(reload_handle),
+        }
     }
 
-    /// Shutdown logger provider
-    pub fn shutdown(&self) {
-        if let Err(e) = self.provider.shutdown() {
-            eprintln!("Failed to shutdown metrics provider: {e}");
+    /// Handle served on `/debug/loglevel` to change the filter at runtime
+    pub fn log_level(&self) -> LogLevel {
+        self.log_level.clone()
+    }
+
+    /// Flush pending log batches and shutdown logger provider within `timeout`
+    pub fn shutdown(&self, timeout: Duration) {
+        if let Err(e) = self.provider.shutdown_with_timeout(timeout) {
+            eprintln!("Failed to shutdown logger provider: {e}");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!(" Text ".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+}
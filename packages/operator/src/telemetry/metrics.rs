@@ -1,93 +1,257 @@
 use opentelemetry::{
-    metrics::{MeterProvider, ObservableGauge},
+    global,
+    metrics::{Meter, MeterProvider, ObservableGauge},
     KeyValue,
 };
-use opentelemetry_otlp::{MetricExporter, Protocol, WithExportConfig};
 use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
-use std::{sync::Arc, time::Duration};
-use sysinfo::{get_current_pid, ProcessesToUpdate, System};
+use prometheus::Registry;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use sysinfo::{get_current_pid, Pid, Process, ProcessRefreshKind, ProcessesToUpdate, System};
+use tokio::runtime::{Handle, RuntimeMetrics};
+use tracing::warn;
+
+use crate::telemetry::{
+    otlp::{or_stdout, OtlpConfig},
+    resource::get_resource,
+};
 
-use crate::telemetry::resource::get_resource;
+#[cfg(all(feature = "tokio-unstable-metrics", not(tokio_unstable)))]
+compile_error!("the tokio-unstable-metrics feature needs RUSTFLAGS=\"--cfg tokio_unstable\"");
 
 /// Struct to hold provider and gauges so their lifetime is explicit
 pub struct MetricsHandle {
     pub provider: SdkMeterProvider,
+    registry: Option<Registry>,
     _cpu_gauge: Arc<ObservableGauge<f64>>,
     _mem_gauge: Arc<ObservableGauge<f64>>,
+    _process_gauges: Vec<ObservableGauge<u64>>,
+    _runtime_gauges: Vec<ObservableGauge<u64>>,
 }
 
 impl MetricsHandle {
     /// Initialize metrics, register gauges, and keep handles alive
-    pub fn init(endpoint: &str) -> Self {
-        let exporter = MetricExporter::builder()
-            .with_http()
-            .with_endpoint(endpoint)
-            .with_protocol(Protocol::HttpBinary)
-            .build()
-            .expect("Failed to create metric exporter");
-
-        let reader = PeriodicReader::builder(exporter)
-            .with_interval(Duration::from_secs(30))
-            .build();
+    /// - `otlp` pushes to an OTLP endpoint every 30 seconds.
+    /// - `pull_enabled` collects into a Prometheus registry served on `/metrics`.
+    pub fn init(otlp: Option<&OtlpConfig>, pull_enabled: bool) -> Self {
+        let mut builder = SdkMeterProvider::builder().with_resource(get_resource());
 
-        let provider = SdkMeterProvider::builder()
-            .with_reader(reader)
-            .with_resource(get_resource())
-            .build();
+        if let Some(exporter) = otlp.and_then(|otlp| or_stdout(otlp.signal, otlp.metric_exporter()))
+        {
+            let reader = PeriodicReader::builder(exporter)
+                .with_interval(Duration::from_secs(30))
+                .build();
+
+            builder = builder.with_reader(reader);
+        }
+
+        // Without an exporter there is nothing to gather, so `/metrics` reports pull mode as disabled
+        let mut registry = pull_enabled.then(Registry::new);
+        if let Some(pull) = &registry {
+            match opentelemetry_prometheus::exporter()
+                .with_registry(pull.clone())
+                .build()
+            {
+                Ok(exporter) => builder = builder.with_reader(exporter),
+                Err(e) => {
+                    warn!("Failed to create prometheus exporter, /metrics is disabled: {e}");
+                    registry = None;
+                }
+            }
+        }
+
+        let provider = builder.build();
+
+        // Make the provider available to instruments created outside this module
+        global::set_meter_provider(provider.clone());
 
         let meter = provider.meter("system-metrics");
+        let probe = Arc::new(ProcessProbe::new());
 
         // CPU gauge
+        let cpu_probe = probe.clone();
         let cpu_gauge = meter
             .f64_observable_gauge("operator_cpu_usage")
             .with_description("CPU usage of this process in %")
             .with_unit("%")
-            .with_callback(|observer| {
-                let mut sys = System::new_all();
-                let pid = get_current_pid().unwrap().as_u32();
-                sys.refresh_processes(
-                    ProcessesToUpdate::Some(&[sysinfo::Pid::from_u32(pid)]),
-                    true,
-                );
-
-                if let Some(proc) = sys.process(sysinfo::Pid::from_u32(pid)) {
-                    let cpu = proc.cpu_usage() as f64;
+            .with_callback(move |observer| {
+                if let Some(cpu) = cpu_probe.read(|proc| proc.cpu_usage() as f64) {
                     observer.observe(cpu, &[KeyValue::new("process", "self")]);
                 }
             })
             .build();
 
         // Memory gauge
+        let mem_probe = probe.clone();
         let mem_gauge = meter
             .f64_observable_gauge("operator_memory")
             .with_description("Memory usage of this process in MB")
             .with_unit("mb")
-            .with_callback(|observer| {
-                let mut sys = System::new_all();
-                let pid = get_current_pid().unwrap().as_u32();
-                sys.refresh_processes(
-                    ProcessesToUpdate::Some(&[sysinfo::Pid::from_u32(pid)]),
-                    true,
-                );
-
-                if let Some(proc) = sys.process(sysinfo::Pid::from_u32(pid)) {
-                    let mem_mb = proc.memory() as f64 / 1048576.0;
+            .with_callback(move |observer| {
+                if let Some(mem_mb) = mem_probe.read(|proc| proc.memory() as f64 / 1048576.0) {
                     observer.observe(mem_mb, &[KeyValue::new("process", "self")]);
                 }
             })
             .build();
 
+        // Thread, file descriptor and uptime gauges
+        let process_gauges = [
+            (
+                "operator_threads",
+                "Number of threads of this process",
+                "{thread}",
+                (|proc: &Process| proc.tasks().map(|tasks| tasks.len() as u64)) as ProcessReading,
+            ),
+            (
+                "operator_open_fds",
+                "Number of open file descriptors of this process",
+                "{fd}",
+                |proc: &Process| proc.open_files().map(|fds| fds as u64),
+            ),
+            (
+                "operator_uptime",
+                "Time since this process started",
+                "s",
+                |proc: &Process| Some(proc.run_time()),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, description, unit, reading)| {
+            let probe = probe.clone();
+            meter
+                .u64_observable_gauge(name)
+                .with_description(description)
+                .with_unit(unit)
+                .with_callback(move |observer| {
+                    if let Some(value) = probe.read(reading).flatten() {
+                        observer.observe(value, &[KeyValue::new("process", "self")]);
+                    }
+                })
+                .build()
+        })
+        .collect();
+
         Self {
             provider,
+            registry,
             _cpu_gauge: Arc::new(cpu_gauge),
             _mem_gauge: Arc::new(mem_gauge),
+            _process_gauges: process_gauges,
+            _runtime_gauges: runtime_gauges(&meter),
         }
     }
 
-    /// Shutdown provider and flush metrics
-    pub fn shutdown(&self) {
-        if let Err(e) = self.provider.shutdown() {
-            eprintln!("Failed to shutdown logger provider: {e}");
+    /// Prometheus registry backing `/metrics`, if pull mode is enabled
+    pub fn registry(&self) -> Option<Registry> {
+        self.registry.clone()
+    }
+
+    /// Flush metrics and shutdown provider within `timeout`
+    pub fn shutdown(&self, timeout: Duration) {
+        if let Err(e) = self.provider.shutdown_with_timeout(timeout) {
+            eprintln!("Failed to shutdown metrics provider: {e}");
         }
     }
 }
+
+/// Reads a single value from the operator process
+type ProcessReading = fn(&Process) -> Option<u64>;
+
+/// Reads a single value from the tokio runtime metrics
+type RuntimeReading = fn(&RuntimeMetrics) -> u64;
+
+/// Shared `System` tracking only the current process.
+/// - Refreshed at most once per `REFRESH_INTERVAL`, so gauges of one collection share a scan.
+/// - Keeping it alive lets sysinfo compute CPU usage from the previous refresh.
+struct ProcessProbe {
+    pid: Option<Pid>,
+    state: Mutex<(System, Option<Instant>)>,
+}
+
+impl ProcessProbe {
+    const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+    fn new() -> Self {
+        Self {
+            pid: get_current_pid().ok(),
+            state: Mutex::new((System::new(), None)),
+        }
+    }
+
+    /// Refresh the current process if stale, then read from it
+    fn read<T>(&self, f: impl FnOnce(&Process) -> T) -> Option<T> {
+        let pid = self.pid?;
+        let mut state = self.state.lock().unwrap();
+        let (system, refreshed_at) = &mut *state;
+
+        if refreshed_at.is_none_or(|at| at.elapsed() >= Self::REFRESH_INTERVAL) {
+            system.refresh_processes_specifics(
+                ProcessesToUpdate::Some(&[pid]),
+                true,
+                ProcessRefreshKind::nothing()
+                    .with_cpu()
+                    .with_memory()
+                    .with_tasks(),
+            );
+            *refreshed_at = Some(Instant::now());
+        }
+
+        system.process(pid).map(f)
+    }
+}
+
+/// Gauges reading the tokio runtime the operator runs on, if any
+fn runtime_gauges(meter: &Meter) -> Vec<ObservableGauge<u64>> {
+    let Ok(handle) = Handle::try_current() else {
+        return Vec::new();
+    };
+
+    let readings: [(&str, &str, RuntimeReading); 3] = [
+        (
+            "operator_tokio_workers",
+            "Number of tokio worker threads",
+            |m| m.num_workers() as u64,
+        ),
+        (
+            "operator_tokio_alive_tasks",
+            "Number of tasks alive in the tokio runtime",
+            |m| m.num_alive_tasks() as u64,
+        ),
+        (
+            "operator_tokio_global_queue_depth",
+            "Number of tasks waiting in the tokio global queue",
+            |m| m.global_queue_depth() as u64,
+        ),
+    ];
+
+    // Blocking pool metrics need `--cfg tokio_unstable`, enforced for the feature below
+    #[cfg(all(feature = "tokio-unstable-metrics", tokio_unstable))]
+    let readings = readings.into_iter().chain([
+        (
+            "operator_tokio_blocking_threads",
+            "Number of threads in the tokio blocking pool",
+            (|m: &RuntimeMetrics| m.num_blocking_threads() as u64) as RuntimeReading,
+        ),
+        (
+            "operator_tokio_blocking_queue_depth",
+            "Number of blocking tasks waiting for a thread",
+            |m: &RuntimeMetrics| m.blocking_queue_depth() as u64,
+        ),
+    ]);
+
+    readings
+        .into_iter()
+        .map(|(name, description, reading)| {
+            let handle = handle.clone();
+            meter
+                .u64_observable_gauge(name)
+                .with_description(description)
+                .with_callback(move |observer| {
+                    observer.observe(reading(&handle.metrics()), &[]);
+                })
+                .build()
+        })
+        .collect()
+}
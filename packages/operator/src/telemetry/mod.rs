@@ -1,3 +1,6 @@
 pub mod logging;
 pub mod metrics;
+pub mod otlp;
+pub mod reconcile_metrics;
 mod resource;
+pub mod traces;
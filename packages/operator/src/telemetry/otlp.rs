@@ -0,0 +1,376 @@
+use anyhow::{bail, Context, Result};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry_otlp::{
+    tonic_types::metadata::MetadataMap, Compression, ExporterBuildError, LogExporter,
+    MetricExporter, Protocol, SpanExporter, WithExportConfig, WithHttpConfig, WithTonicConfig,
+};
+use std::{collections::HashMap, env, fmt, time::Duration};
+
+/// Telemetry signal exported over OTLP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Logs,
+    Metrics,
+    Traces,
+}
+
+impl Signal {
+    /// Upper-case name used in signal specific `OTEL_EXPORTER_OTLP_<SIGNAL>_*` variables
+    fn env_name(self) -> &'static str {
+        match self {
+            Signal::Logs => "LOGS",
+            Signal::Metrics => "METRICS",
+            Signal::Traces => "TRACES",
+        }
+    }
+
+    /// Path appended to `OTEL_EXPORTER_OTLP_ENDPOINT` for HTTP exporters
+    fn http_path(self) -> &'static str {
+        match self {
+            Signal::Logs => "/v1/logs",
+            Signal::Metrics => "/v1/metrics",
+            Signal::Traces => "/v1/traces",
+        }
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.env_name().to_lowercase())
+    }
+}
+
+/// OTLP exporter settings for one signal, resolved from the standard `OTEL_*` variables
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtlpConfig {
+    pub signal: Signal,
+    pub endpoint: String,
+    pub protocol: Protocol,
+    pub headers: HashMap<String, String>,
+    pub timeout: Duration,
+    pub compression: Option<Compression>,
+}
+
+impl OtlpConfig {
+    /// Resolve the exporter for `signal` from the process environment.
+    /// - Returns `None` when the SDK or the signal is disabled, or neither an endpoint nor the
+    ///   `otlp` exporter is configured, in which case the operator only writes to stdout.
+    /// - Malformed values are reported and the signal also falls back to stdout only.
+    pub fn from_env(signal: Signal) -> Option<Self> {
+        Self::resolve_or_stdout(signal, |key| env::var(key).ok())
+    }
+
+    fn resolve_or_stdout(signal: Signal, lookup: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        // Tracing is not set up yet while the configuration loads
+        Self::resolve(signal, lookup).unwrap_or_else(|e| {
+            eprintln!("Invalid OTLP {signal} configuration, continuing with stdout only: {e:#}");
+            None
+        })
+    }
+
+    fn resolve(signal: Signal, lookup: impl Fn(&str) -> Option<String>) -> Result<Option<Self>> {
+        let name = signal.env_name();
+        let var = |suffix: &str| {
+            lookup(&format!("OTEL_EXPORTER_OTLP_{name}_{suffix}"))
+                .or_else(|| lookup(&format!("OTEL_EXPORTER_OTLP_{suffix}")))
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+
+        if lookup("OTEL_SDK_DISABLED").is_some_and(|value| value.trim() == "true") {
+            return Ok(None);
+        }
+
+        // `OTEL_<SIGNAL>_EXPORTER` selects the exporter; a URL is accepted as a legacy endpoint
+        let exporter = lookup(&format!("OTEL_{name}_EXPORTER"));
+        let legacy_endpoint = match exporter.as_deref().map(str::trim) {
+            Some("none") | Some("console") => return Ok(None),
+            Some(value) if value.contains("://") => Some(value.to_string()),
+            _ => None,
+        };
+
+        let protocol = match var("PROTOCOL").as_deref() {
+            None | Some("http/protobuf") => Protocol::HttpBinary,
+            Some("http/json") => Protocol::HttpJson,
+            Some("grpc") => Protocol::Grpc,
+            Some(other) => bail!("Unsupported OTLP protocol '{other}' for {signal}"),
+        };
+
+        let endpoint = match lookup(&format!("OTEL_EXPORTER_OTLP_{name}_ENDPOINT")) {
+            Some(endpoint) => endpoint,
+            None => match (legacy_endpoint, lookup("OTEL_EXPORTER_OTLP_ENDPOINT")) {
+                (Some(endpoint), _) => endpoint,
+                (None, Some(base)) if protocol == Protocol::Grpc => base,
+                (None, Some(base)) => {
+                    format!("{}{}", base.trim_end_matches('/'), signal.http_path())
+                }
+                // An explicit `otlp` exporter uses the specification's local collector defaults
+                (None, None) if exporter.as_deref().map(str::trim) == Some("otlp") => {
+                    match protocol {
+                        Protocol::Grpc => "http://localhost:4317".to_string(),
+                        _ => format!("http://localhost:4318{}", signal.http_path()),
+                    }
+                }
+                (None, None) => return Ok(None),
+            },
+        };
+
+        let headers = var("HEADERS")
+            .map(|value| parse_headers(&value))
+            .transpose()
+            .with_context(|| format!("Failed to parse OTLP headers for {signal}"))?
+            .unwrap_or_default();
+
+        let timeout = match var("TIMEOUT") {
+            Some(value) => value.parse().map(Duration::from_millis).with_context(|| {
+                format!("Failed to parse OTLP timeout for {signal} from '{value}'")
+            })?,
+            None => Duration::from_secs(10),
+        };
+
+        let compression = match var("COMPRESSION").as_deref() {
+            None | Some("none") => None,
+            Some(value) => Some(
+                value
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("{e}"))
+                    .with_context(|| format!("Unsupported OTLP compression for {signal}"))?,
+            ),
+        };
+
+        Ok(Some(Self {
+            signal,
+            endpoint,
+            protocol,
+            headers,
+            timeout,
+            compression,
+        }))
+    }
+
+    pub fn log_exporter(&self) -> Result<LogExporter, ExporterBuildError> {
+        match self.protocol {
+            Protocol::Grpc => self.tonic(LogExporter::builder().with_tonic()).build(),
+            _ => self.http(LogExporter::builder().with_http()).build(),
+        }
+    }
+
+    pub fn metric_exporter(&self) -> Result<MetricExporter, ExporterBuildError> {
+        match self.protocol {
+            Protocol::Grpc => self.tonic(MetricExporter::builder().with_tonic()).build(),
+            _ => self.http(MetricExporter::builder().with_http()).build(),
+        }
+    }
+
+    pub fn span_exporter(&self) -> Result<SpanExporter, ExporterBuildError> {
+        match self.protocol {
+            Protocol::Grpc => self.tonic(SpanExporter::builder().with_tonic()).build(),
+            _ => self.http(SpanExporter::builder().with_http()).build(),
+        }
+    }
+
+    fn http<B: WithExportConfig + WithHttpConfig>(&self, builder: B) -> B {
+        let builder = builder
+            .with_endpoint(&self.endpoint)
+            .with_protocol(self.protocol)
+            .with_timeout(self.timeout)
+            .with_headers(self.headers.clone());
+
+        match self.compression {
+            Some(compression) => builder.with_compression(compression),
+            None => builder,
+        }
+    }
+
+    fn tonic<B: WithExportConfig + WithTonicConfig>(&self, builder: B) -> B {
+        // `parse_headers` rejected invalid names and values, so none are dropped here
+        let mut headers = HeaderMap::new();
+        for (key, value) in &self.headers {
+            if let (Ok(key), Ok(value)) = (
+                HeaderName::from_bytes(key.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.insert(key, value);
+            }
+        }
+
+        let builder = builder
+            .with_endpoint(&self.endpoint)
+            .with_protocol(self.protocol)
+            .with_timeout(self.timeout)
+            .with_metadata(MetadataMap::from_headers(headers));
+
+        match self.compression {
+            Some(compression) => builder.with_compression(compression),
+            None => builder,
+        }
+    }
+}
+
+/// Keep a freshly built exporter, or warn and continue with stdout only
+pub fn or_stdout<T>(signal: Signal, exporter: Result<T, ExporterBuildError>) -> Option<T> {
+    match exporter {
+        Ok(exporter) => Some(exporter),
+        Err(e) => {
+            eprintln!("Failed to create OTLP {signal} exporter, continuing with stdout only: {e}");
+            None
+        }
+    }
+}
+
+/// Parse `key1=value1,key2=value2`, percent-decoding keys and values.
+/// - Names and values must be valid HTTP headers, the gRPC exporter could not send them otherwise.
+fn parse_headers(value: &str) -> Result<HashMap<String, String>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair
+                .split_once('=')
+                .with_context(|| format!("Header '{pair}' is not in key=value form"))?;
+            let (key, value) = (percent_decode(key.trim())?, percent_decode(value.trim())?);
+            HeaderName::from_bytes(key.as_bytes())
+                .with_context(|| format!("Invalid header name '{key}'"))?;
+            HeaderValue::from_str(&value)
+                .with_context(|| format!("Invalid value for header '{key}'"))?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> Result<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = value
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .with_context(|| format!("Invalid percent-encoding in '{value}'"))?;
+            decoded.push(hex);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).with_context(|| format!("Invalid UTF-8 in '{value}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(signal: Signal, vars: &[(&str, &str)]) -> Result<Option<OtlpConfig>> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        OtlpConfig::resolve(signal, |key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn test_no_endpoint_means_stdout_only() {
+        assert_eq!(resolve(Signal::Logs, &[]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_sdk_disabled() {
+        let vars = [
+            ("OTEL_SDK_DISABLED", "true"),
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318"),
+        ];
+        assert_eq!(resolve(Signal::Traces, &vars).unwrap(), None);
+    }
+
+    #[test]
+    fn test_base_endpoint_gets_signal_path_for_http() {
+        let vars = [("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318/")];
+        let config = resolve(Signal::Metrics, &vars).unwrap().unwrap();
+        assert_eq!(config.endpoint, "http://collector:4318/v1/metrics");
+        assert_eq!(config.protocol, Protocol::HttpBinary);
+    }
+
+    #[test]
+    fn test_signal_settings_override_shared_ones() {
+        let vars = [
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4317"),
+            ("OTEL_EXPORTER_OTLP_PROTOCOL", "http/protobuf"),
+            ("OTEL_EXPORTER_OTLP_TRACES_PROTOCOL", "grpc"),
+            ("OTEL_EXPORTER_OTLP_TIMEOUT", "2500"),
+            ("OTEL_EXPORTER_OTLP_COMPRESSION", "gzip"),
+            (
+                "OTEL_EXPORTER_OTLP_HEADERS",
+                "authorization=Basic%20abc,x-tenant=blue",
+            ),
+        ];
+        let config = resolve(Signal::Traces, &vars).unwrap().unwrap();
+        assert_eq!(config.endpoint, "http://collector:4317");
+        assert_eq!(config.protocol, Protocol::Grpc);
+        assert_eq!(config.timeout, Duration::from_millis(2500));
+        assert_eq!(config.compression, Some(Compression::Gzip));
+        assert_eq!(config.headers["authorization"], "Basic abc");
+        assert_eq!(config.headers["x-tenant"], "blue");
+    }
+
+    #[test]
+    fn test_legacy_exporter_variable() {
+        let legacy = [("OTEL_LOGS_EXPORTER", "http://loki:4318/v1/logs")];
+        let config = resolve(Signal::Logs, &legacy).unwrap().unwrap();
+        assert_eq!(config.endpoint, "http://loki:4318/v1/logs");
+
+        let disabled = [
+            ("OTEL_LOGS_EXPORTER", "none"),
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318"),
+        ];
+        assert_eq!(resolve(Signal::Logs, &disabled).unwrap(), None);
+    }
+
+    #[test]
+    fn test_otlp_exporter_defaults_to_local_collector() {
+        let http = [("OTEL_TRACES_EXPORTER", "otlp")];
+        let config = resolve(Signal::Traces, &http).unwrap().unwrap();
+        assert_eq!(config.endpoint, "http://localhost:4318/v1/traces");
+
+        let grpc = [
+            ("OTEL_METRICS_EXPORTER", "otlp"),
+            ("OTEL_EXPORTER_OTLP_PROTOCOL", "grpc"),
+        ];
+        let config = resolve(Signal::Metrics, &grpc).unwrap().unwrap();
+        assert_eq!(config.endpoint, "http://localhost:4317");
+    }
+
+    #[test]
+    fn test_invalid_headers() {
+        for headers in ["bad name=value", "x-tenant=line%0Abreak"] {
+            let vars = [
+                ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318"),
+                ("OTEL_EXPORTER_OTLP_HEADERS", headers),
+            ];
+            assert!(resolve(Signal::Logs, &vars).is_err(), "{headers}");
+        }
+    }
+
+    #[test]
+    fn test_malformed_values_fall_back_to_stdout() {
+        let vars: HashMap<&str, &str> = [
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318"),
+            ("OTEL_EXPORTER_OTLP_LOGS_TIMEOUT", "soon"),
+        ]
+        .into();
+        let lookup = |key: &str| vars.get(key).map(|value| value.to_string());
+        assert_eq!(OtlpConfig::resolve_or_stdout(Signal::Logs, lookup), None);
+        assert!(OtlpConfig::resolve_or_stdout(Signal::Traces, lookup).is_some());
+    }
+
+    #[test]
+    fn test_invalid_protocol() {
+        let vars = [
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318"),
+            ("OTEL_EXPORTER_OTLP_PROTOCOL", "carrier-pigeon"),
+        ];
+        assert!(resolve(Signal::Logs, &vars).is_err());
+    }
+}
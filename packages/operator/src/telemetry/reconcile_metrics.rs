@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use kube::ResourceExt;
+use kube_runtime::reflector::Store;
+use opentelemetry::{
+    global,
+    metrics::{Counter, Histogram, ObservableGauge, UpDownCounter},
+    KeyValue,
+};
+
+use crate::crds::crd::Moodle;
+
+/// Phase reported for Moodle objects that have no status yet
+const PHASE_PENDING: &str = "Pending";
+
+/// Reconcile instruments shared by `reconcile` and `error_policy`
+#[derive(Clone)]
+pub struct ReconcileMetrics {
+    duration: Histogram<f64>,
+    reconciles: Counter<u64>,
+    failures: Counter<u64>,
+    queued: UpDownCounter<i64>,
+    in_flight: UpDownCounter<i64>,
+    stores: Arc<RwLock<Vec<Store<Moodle>>>>,
+    _phase_gauge: Arc<ObservableGauge<u64>>,
+    _desired_gauge: Arc<ObservableGauge<i64>>,
+    _ready_gauge: Arc<ObservableGauge<i64>>,
+    _paused_gauge: Arc<ObservableGauge<u64>>,
+}
+
+impl ReconcileMetrics {
+    /// Register reconcile instruments on the global meter provider.
+    /// - Gauges are computed from the controller caches registered with `watch_stores`.
+    pub fn init() -> Self {
+        let meter = global::meter("moodle-operator");
+        let stores: Arc<RwLock<Vec<Store<Moodle>>>> = Arc::default();
+
+        let duration = meter
+            .f64_histogram("moodle_reconcile_duration")
+            .with_description("Duration of Moodle reconcile passes")
+            .with_unit("s")
+            .with_boundaries(vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0])
+            .build();
+
+        let reconciles = meter
+            .u64_counter("moodle_reconcile")
+            .with_description("Moodle reconcile passes by result")
+            .build();
+
+        let failures = meter
+            .u64_counter("moodle_reconcile_errors")
+            .with_description("Failed Moodle reconcile passes by error kind")
+            .build();
+
+        let queued = meter
+            .i64_up_down_counter("moodle_reconcile_queue_depth")
+            .with_description("Moodle reconciles waiting for a RECONCILE_CONCURRENCY slot")
+            .build();
+
+        let in_flight = meter
+            .i64_up_down_counter("moodle_reconcile_in_flight")
+            .with_description("Moodle reconciles currently running")
+            .build();
+
+        let phase_stores = stores.clone();
+        let phase_gauge = meter
+            .u64_observable_gauge("moodle_objects")
+            .with_description("Moodle objects by status phase")
+            .with_callback(move |observer| {
+                let mut phases: BTreeMap<String, u64> = BTreeMap::new();
+                for moodle in cached(&phase_stores) {
+                    let phase = moodle
+                        .status
+                        .as_ref()
+                        .and_then(|status| status.phase.clone())
+                        .unwrap_or_else(|| PHASE_PENDING.to_string());
+                    *phases.entry(phase).or_default() += 1;
+                }
+                for (phase, count) in phases {
+                    observer.observe(count, &[KeyValue::new("phase", phase)]);
+                }
+            })
+            .build();
+
+        let desired_stores = stores.clone();
+        let desired_gauge = meter
+            .i64_observable_gauge("moodle_replicas_desired")
+            .with_description("Replicas requested in the Moodle spec")
+            .with_callback(move |observer| {
+                for moodle in cached(&desired_stores) {
+                    observer.observe(moodle.spec.replicas as i64, &site_labels(&moodle));
+                }
+            })
+            .build();
+
+        let ready_stores = stores.clone();
+        let ready_gauge = meter
+            .i64_observable_gauge("moodle_replicas_ready")
+            .with_description("Ready replicas reported in the Moodle status")
+            .with_callback(move |observer| {
+                for moodle in cached(&ready_stores) {
+                    let ready = moodle
+                        .status
+                        .as_ref()
+                        .and_then(|status| status.ready_replicas)
+                        .unwrap_or(0);
+                    observer.observe(ready as i64, &site_labels(&moodle));
+                }
+            })
+            .build();
+
+        let paused_stores = stores.clone();
+        let paused_gauge = meter
+            .u64_observable_gauge("moodle_paused")
+            .with_description("Moodle objects whose reconciliation is paused by annotation")
+            .with_callback(move |observer| {
+                let paused = cached(&paused_stores)
+                    .iter()
+                    .filter(|moodle| moodle.is_paused())
+                    .count();
+                observer.observe(paused as u64, &[]);
+            })
+            .build();
+
+        Self {
+            duration,
+            reconciles,
+            failures,
+            queued,
+            in_flight,
+            stores,
+            _phase_gauge: Arc::new(phase_gauge),
+            _desired_gauge: Arc::new(desired_gauge),
+            _ready_gauge: Arc::new(ready_gauge),
+            _paused_gauge: Arc::new(paused_gauge),
+        }
+    }
+
+    /// Use these controller caches as the source for the per-Moodle gauges
+    pub fn watch_stores(&self, stores: Vec<Store<Moodle>>) {
+        *self.stores.write().unwrap() = stores;
+    }
+
+    /// Record the duration and result of one reconcile pass
+    pub fn record_reconcile(&self, elapsed: Duration, success: bool) {
+        let result = KeyValue::new("result", if success { "success" } else { "failure" });
+        self.duration
+            .record(elapsed.as_secs_f64(), std::slice::from_ref(&result));
+        self.reconciles.add(1, &[result]);
+    }
+
+    /// Count a reconcile as queued until `ReconcileSlot::start`, then as running until dropped
+    pub fn enqueue(&self) -> ReconcileSlot {
+        self.queued.add(1, &[]);
+        ReconcileSlot {
+            metrics: self.clone(),
+            running: false,
+        }
+    }
+
+    /// Count a failed reconcile by error kind
+    pub fn record_failure(&self, error_kind: &'static str) {
+        self.failures
+            .add(1, &[KeyValue::new("error_kind", error_kind)]);
+    }
+}
+
+/// One reconcile tracked by the queue depth and in-flight gauges
+pub struct ReconcileSlot {
+    metrics: ReconcileMetrics,
+    running: bool,
+}
+
+impl ReconcileSlot {
+    /// The reconcile got its concurrency slot and runs now
+    pub fn start(&mut self) {
+        if !self.running {
+            self.running = true;
+            self.metrics.queued.add(-1, &[]);
+            self.metrics.in_flight.add(1, &[]);
+        }
+    }
+}
+
+impl Drop for ReconcileSlot {
+    fn drop(&mut self) {
+        match self.running {
+            true => self.metrics.in_flight.add(-1, &[]),
+            false => self.metrics.queued.add(-1, &[]),
+        }
+    }
+}
+
+fn cached(stores: &RwLock<Vec<Store<Moodle>>>) -> Vec<Arc<Moodle>> {
+    stores
+        .read()
+        .unwrap()
+        .iter()
+        .flat_map(|store| store.state())
+        .collect()
+}
+
+fn site_labels(moodle: &Moodle) -> [KeyValue; 2] {
+    [
+        KeyValue::new("moodle", moodle.name_any()),
+        KeyValue::new("namespace", moodle.namespace().unwrap_or_default()),
+    ]
+}
@@ -1,14 +1,68 @@
-use opentelemetry_sdk::Resource;
-use std::sync::OnceLock;
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::{
+    resource::{EnvResourceDetector, TelemetryResourceDetector},
+    Resource,
+};
+use std::{env, sync::OnceLock};
 
 static RESOURCE: OnceLock<Resource> = OnceLock::new();
 
+/// Downward-API variables and the resource attributes they populate
+const KUBERNETES_ATTRIBUTES: [(&str, &str); 4] = [
+    ("POD_NAME", "k8s.pod.name"),
+    ("POD_NAMESPACE", "k8s.namespace.name"),
+    ("NODE_NAME", "k8s.node.name"),
+    ("CLUSTER_NAME", "k8s.cluster.name"),
+];
+
+/// Resource shared by the log, metric and trace providers.
+/// - Identifies the operator version and the pod, namespace, node and cluster it runs in.
+/// - `OTEL_RESOURCE_ATTRIBUTES` is applied last, so it can override any detected attribute.
 pub fn get_resource() -> Resource {
     RESOURCE
         .get_or_init(|| {
-            Resource::builder()
+            Resource::builder_empty()
+                .with_detector(Box::new(TelemetryResourceDetector))
                 .with_service_name("moodle-operator")
+                .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+                .with_attributes(kubernetes_attributes(|key| env::var(key).ok()))
+                .with_detector(Box::new(EnvResourceDetector::new()))
                 .build()
         })
         .clone()
 }
+
+fn kubernetes_attributes(lookup: impl Fn(&str) -> Option<String>) -> Vec<KeyValue> {
+    KUBERNETES_ATTRIBUTES
+        .iter()
+        .filter_map(|(var, attribute)| {
+            lookup(var)
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .map(|value| KeyValue::new(*attribute, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kubernetes_attributes_skip_unset_variables() {
+        let attributes = kubernetes_attributes(|key| match key {
+            "POD_NAME" => Some("moodle-operator-7d9f".to_string()),
+            "NODE_NAME" => Some("worker-1".to_string()),
+            "CLUSTER_NAME" => Some(" ".to_string()),
+            _ => None,
+        });
+
+        assert_eq!(
+            attributes,
+            vec![
+                KeyValue::new("k8s.pod.name", "moodle-operator-7d9f"),
+                KeyValue::new("k8s.node.name", "worker-1"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,41 @@
+use opentelemetry::{global, trace::TracerProvider};
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use std::time::Duration;
+
+use crate::telemetry::{
+    otlp::{or_stdout, OtlpConfig},
+    resource::get_resource,
+};
+
+/// Struct to hold the tracer provider to keep it alive
+pub struct TracerHandle {
+    pub provider: SdkTracerProvider,
+}
+
+impl TracerHandle {
+    /// Initialize tracing with an OTLP span exporter, if `otlp` is set.
+    /// - Registers the provider globally so spans from `tracing` are exported.
+    pub fn init(otlp: Option<&OtlpConfig>) -> Self {
+        let mut builder = SdkTracerProvider::builder().with_resource(get_resource());
+        if let Some(exporter) = otlp.and_then(|otlp| or_stdout(otlp.signal, otlp.span_exporter())) {
+            builder = builder.with_batch_exporter(exporter);
+        }
+        let provider = builder.build();
+
+        global::set_tracer_provider(provider.clone());
+
+        Self { provider }
+    }
+
+    /// Tracer used by the `tracing` bridge layer
+    pub fn tracer(&self) -> SdkTracer {
+        self.provider.tracer("moodle-operator")
+    }
+
+    /// Flush pending spans and shutdown tracer provider within `timeout`
+    pub fn shutdown(&self, timeout: Duration) {
+        if let Err(e) = self.provider.shutdown_with_timeout(timeout) {
+            eprintln!("Failed to shutdown tracer provider: {e}");
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use kube::core::{
+    admission::{
+        AdmissionRequest, AdmissionResponse, AdmissionReview, ConvertAdmissionReviewError,
+        Operation,
+    },
+    conversion::{ConversionRequest, ConversionResponse, ConversionReview},
+    response::{StatusCause, StatusDetails},
+    DynamicObject, Status,
+};
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::{
+    config::SpecDefaults,
+    crds::{
+        conversion::convert,
+        crd::{FieldError, Moodle, MoodleSpec},
+    },
+};
+
+/// Answer a `ValidatingAdmissionWebhook` review of a Moodle object.
+/// - Fields left empty are checked with `defaults` applied, as the reconciler would see them.
+pub fn validate(body: &[u8], defaults: &SpecDefaults) -> AdmissionReview<DynamicObject> {
+    let request = match parse(body) {
+        Ok(request) => request,
+        Err(e) => return AdmissionResponse::invalid(e).into_review(),
+    };
+
+    let response = AdmissionResponse::from(&request);
+    let violations = match (&request.operation, &request.object) {
+        (Operation::Create | Operation::Update, Some(moodle)) => violations(
+            // Old objects stored before the mutating webhook was registered lack the defaults
+            request
+                .old_object
+                .as_ref()
+                .map(|old| defaults.applied(old).spec)
+                .as_ref(),
+            &defaults.applied(moodle).spec,
+        ),
+        _ => Vec::new(),
+    };
+
+    if violations.is_empty() {
+        return response.into_review();
+    }
+
+    info!(
+        "Rejected {:?} of Moodle {}/{}: {} violation(s)",
+        request.operation,
+        request.namespace.as_deref().unwrap_or_default(),
+        request.name,
+        violations.len()
+    );
+    deny(response, &violations).into_review()
+}
+
+/// Answer a `MutatingAdmissionWebhook` review of a Moodle object.
+/// - Patches empty spec fields with `defaults`, so they are visible on the stored object.
+pub fn mutate(body: &[u8], defaults: &SpecDefaults) -> AdmissionReview<DynamicObject> {
+    let request = match parse(body) {
+        Ok(request) => request,
+        Err(e) => return AdmissionResponse::invalid(e).into_review(),
+    };
+
+    let response = AdmissionResponse::from(&request);
+    let Some(moodle) = request
+        .object
+        .as_ref()
+        .filter(|_| matches!(request.operation, Operation::Create | Operation::Update))
+    else {
+        return response.into_review();
+    };
+
+    let spec = &moodle.spec;
+    let defaulted = defaults.applied(moodle).spec;
+
+    // `add` also works for members missing from the submitted object, unlike `replace`
+    let mut operations = Vec::new();
+    if defaulted.image != spec.image {
+        operations.push(json!({ "op": "add", "path": "/spec/image", "value": defaulted.image }));
+    }
+    if defaulted.service_type != spec.service_type {
+        operations.push(
+            json!({ "op": "add", "path": "/spec/serviceType", "value": defaulted.service_type }),
+        );
+    }
+    if defaulted.pvc_name != spec.pvc_name {
+        operations
+            .push(json!({ "op": "add", "path": "/spec/pvcName", "value": defaulted.pvc_name }));
+    }
+    if defaulted.database.port != spec.database.port {
+        operations.push(
+            json!({ "op": "add", "path": "/spec/database/port", "value": defaulted.database.port }),
+        );
+    }
+    if operations.is_empty() {
+        return response.into_review();
+    }
+
+    let patch: json_patch::Patch = match serde_json::from_value(Value::Array(operations)) {
+        Ok(patch) => patch,
+        Err(e) => return response.deny(e).into_review(),
+    };
+    match response.clone().with_patch(patch) {
+        Ok(response) => response.into_review(),
+        Err(e) => response.deny(e).into_review(),
+    }
+}
+
+/// Answer a CRD `ConversionReview`, converting every object to the desired Moodle version.
+/// - One failed object fails the whole review, as the API server requires.
+pub fn conversion(body: &[u8]) -> ConversionReview {
+    let request = match serde_json::from_slice::<ConversionReview>(body)
+        .map_err(|e| e.to_string())
+        .and_then(|review| ConversionRequest::from_review(review).map_err(|e| e.to_string()))
+    {
+        Ok(request) => request,
+        Err(e) => {
+            return ConversionResponse::invalid(Status::failure(&e, "BadRequest")).into_review()
+        }
+    };
+
+    let desired = request.desired_api_version.clone();
+    let objects = request.objects.clone();
+    let response = ConversionResponse::for_request(request);
+    match objects
+        .into_iter()
+        .map(|object| convert(object, &desired))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(converted) => response.success(converted).into_review(),
+        Err(e) => {
+            info!("Rejected conversion of Moodle objects to {desired}: {e}");
+            response
+                .failure(Status::failure(&e, "ConversionFailed"))
+                .into_review()
+        }
+    }
+}
+
+fn parse(body: &[u8]) -> Result<AdmissionRequest<Moodle>, String> {
+    let review: AdmissionReview<Moodle> =
+        serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    review
+        .try_into()
+        .map_err(|e: ConvertAdmissionReviewError| e.to_string())
+}
+
+/// Problems with `spec`, including update-only rules when replacing `old`
+pub fn violations(old: Option<&MoodleSpec>, spec: &MoodleSpec) -> Vec<FieldError> {
+    let mut violations = Vec::new();
+    if let Err(errors) = spec.validate() {
+        violations.extend(errors.0);
+    }
+    if let Some(Err(errors)) = old.map(|old| spec.validate_update(old)) {
+        violations.extend(errors.0);
+    }
+    violations
+}
+
+/// Deny with one message per field, also listed as causes for API clients
+fn deny(response: AdmissionResponse, violations: &[FieldError]) -> AdmissionResponse {
+    let message = violations
+        .iter()
+        .map(FieldError::to_string)
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    let mut response = response.deny(message);
+    response.result.code = 422;
+    response.result.reason = "Invalid".to_string();
+    response.result.details = Some(StatusDetails {
+        name: String::new(),
+        group: String::new(),
+        kind: String::new(),
+        uid: String::new(),
+        causes: violations
+            .iter()
+            .map(|violation| StatusCause {
+                reason: violation.code.as_str().to_string(),
+                message: violation.message.clone(),
+                field: violation.path.clone(),
+            })
+            .collect(),
+        retry_after_seconds: 0,
+    });
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crds::crd::{DatabaseConfig, DatabaseType, ServiceType};
+    use std::collections::BTreeMap;
+
+    fn defaults() -> SpecDefaults {
+        SpecDefaults {
+            image: "bitnami/moodle:5.0".to_string(),
+            service_type: ServiceType::ClusterIP,
+            db_ports: BTreeMap::new(),
+            pvc_suffix: "-data".to_string(),
+        }
+    }
+
+    fn review(object: serde_json::Value) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "apiVersion": "admission.k8s.io/v1",
+            "kind": "AdmissionReview",
+            "request": {
+                "uid": "0df28fbd",
+                "kind": { "group": "moodle.adorsys.com", "version": "v1", "kind": "Moodle" },
+                "resource": { "group": "moodle.adorsys.com", "version": "v1", "resource": "moodles" },
+                "name": "site",
+                "namespace": "default",
+                "operation": "CREATE",
+                "userInfo": {},
+                "object": object,
+                "dryRun": false
+            }
+        }))
+        .unwrap()
+    }
+
+    fn spec() -> MoodleSpec {
+        MoodleSpec {
+            image: "bitnami/moodle:4.5".to_string(),
+            replicas: 1,
+            service_type: Some(ServiceType::ClusterIP),
+            pvc_name: "moodle-data".to_string(),
+            database: DatabaseConfig {
+                host: "db".to_string(),
+                port: 5432,
+                user: "moodle".to_string(),
+                password: "secret".to_string(),
+                password_secret_ref: None,
+                db_type: DatabaseType::Pgsql,
+                name: "moodle".to_string(),
+            },
+            env_config_map: None,
+        }
+    }
+
+    #[test]
+    fn test_valid_create() {
+        assert!(violations(None, &spec()).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_spec() {
+        let mut invalid = spec();
+        invalid.image = " ".to_string();
+        invalid.database.user = String::new();
+        let fields: Vec<String> = violations(None, &invalid)
+            .into_iter()
+            .map(|violation| violation.path)
+            .collect();
+        assert_eq!(fields, ["spec.image", "spec.database.user"]);
+    }
+
+    #[test]
+    fn test_immutable_fields_on_update() {
+        let old = spec();
+        let mut new = spec();
+        new.replicas = 3;
+        assert!(violations(Some(&old), &new).is_empty());
+
+        new.pvc_name = "other-data".to_string();
+        new.database.db_type = DatabaseType::Mariadb;
+        let fields: Vec<String> = violations(Some(&old), &new)
+            .into_iter()
+            .map(|violation| violation.path)
+            .collect();
+        assert_eq!(fields, ["spec.pvcName", "spec.database.type"]);
+    }
+
+    #[test]
+    fn test_conversion_review() {
+        let object = json!({
+            "apiVersion": "moodle.adorsys.com/v1",
+            "kind": "Moodle",
+            "metadata": { "name": "site", "namespace": "default" },
+            "spec": serde_json::to_value(spec()).unwrap()
+        });
+        let body = serde_json::to_vec(&json!({
+            "apiVersion": "apiextensions.k8s.io/v1",
+            "kind": "ConversionReview",
+            "request": {
+                "uid": "5c1e4b7a",
+                "desiredAPIVersion": "moodle.adorsys.com/v1beta1",
+                "objects": [object]
+            }
+        }))
+        .unwrap();
+
+        let response = conversion(&body).response.unwrap();
+        assert_eq!(response.uid, "5c1e4b7a");
+        assert!(response.result.is_success());
+        let converted = &response.converted_objects[0];
+        assert_eq!(converted["apiVersion"], "moodle.adorsys.com/v1beta1");
+        assert_eq!(converted["spec"]["storage"]["pvcName"], "moodle-data");
+    }
+
+    #[test]
+    fn test_mutate_fills_empty_fields() {
+        let body = review(json!({
+            "apiVersion": "moodle.adorsys.com/v1",
+            "kind": "Moodle",
+            "metadata": { "name": "site", "namespace": "default" },
+            "spec": {
+                "database": {
+                    "host": "db", "user": "moodle", "password": "secret",
+                    "type": "pgsql", "name": "moodle"
+                }
+            }
+        }));
+
+        let response = mutate(&body, &defaults()).response.unwrap();
+        assert!(response.allowed);
+        let patch: serde_json::Value = serde_json::from_slice(&response.patch.unwrap()).unwrap();
+        assert!(patch.as_array().unwrap().iter().all(|op| op["op"] == "add"));
+        let paths: Vec<&str> = patch
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|op| op["path"].as_str().unwrap())
+            .collect();
+        assert!(paths.contains(&"/spec/image"));
+        assert!(paths.contains(&"/spec/serviceType"));
+        assert!(paths.contains(&"/spec/database/port"));
+        let pvc_name = patch
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|op| op["path"] == "/spec/pvcName");
+        assert_eq!(pvc_name.unwrap()["value"], "site-data");
+
+        let response = validate(&body, &defaults()).response.unwrap();
+        assert!(response.allowed);
+    }
+}